@@ -0,0 +1,81 @@
+#![forbid(unsafe_code)]
+use std::{cmp::Reverse, collections::VecDeque, hint::black_box, time::Instant};
+
+#[path = "../standard/waiter_queue.rs"]
+#[allow(dead_code)]
+mod waiter_queue;
+use waiter_queue::WaiterQueue;
+
+const WAITERS: usize = 10_000;
+
+/// Deterministic pseudo-random priorities, a handful of distinct levels like in the game.
+fn priorities() -> Vec<i64> {
+    let mut x: u64 = 0x2545F4914F6CDD1D;
+    (0..WAITERS)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x % 8) as i64
+        })
+        .collect()
+}
+
+/// Push all the waiters, popping one every other push, then drain the queue. Returns the order in
+/// which waiters were served.
+fn run_sorted_vecdeque(keys: &[i64]) -> Vec<usize> {
+    let mut queue = VecDeque::new();
+    let mut served = vec![];
+    for (i, &key) in keys.iter().enumerate() {
+        queue.push_back((key, i));
+        queue
+            .make_contiguous()
+            .sort_by_key(|(key, _)| Reverse(*key));
+        if i % 2 == 1 {
+            served.extend(queue.pop_front().map(|(_, i)| i));
+        }
+    }
+    served.extend(queue.into_iter().map(|(_, i)| i));
+    served
+}
+
+fn run_waiter_queue(keys: &[i64]) -> Vec<usize> {
+    let mut queue = WaiterQueue::default();
+    let mut served = vec![];
+    for (i, &key) in keys.iter().enumerate() {
+        queue.push(key, i);
+        if i % 2 == 1 {
+            served.extend(queue.pop());
+        }
+    }
+    while let Some(i) = queue.pop() {
+        served.push(i);
+    }
+    served
+}
+
+/// Compares `WaiterQueue` with the sorted `VecDeque` that `ProducerWithQueue` used to have. Run
+/// with `cargo run --release --bin queue_bench`.
+fn main() {
+    let keys = priorities();
+
+    let start = Instant::now();
+    let expected = black_box(run_sorted_vecdeque(black_box(&keys)));
+    let vecdeque_time = start.elapsed();
+
+    let start = Instant::now();
+    let actual = black_box(run_waiter_queue(black_box(&keys)));
+    let heap_time = start.elapsed();
+
+    assert_eq!(
+        expected, actual,
+        "the two queues served waiters in a different order"
+    );
+    println!("{WAITERS} waiters:");
+    println!("  sorted VecDeque: {vecdeque_time:?}");
+    println!("  WaiterQueue:     {heap_time:?}");
+    println!(
+        "  speedup:         {:.1}x",
+        vecdeque_time.as_secs_f64() / heap_time.as_secs_f64()
+    );
+}
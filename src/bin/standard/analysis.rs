@@ -1,10 +1,11 @@
 use std::collections::hash_map::Entry;
 
+use indexmap::IndexMap;
 use itertools::Itertools;
 use petgraph::{
-    matrix_graph::Zero,
+    algo::tarjan_scc,
     prelude::DiGraphMap,
-    visit::{DfsPostOrder, Walker},
+    visit::{Dfs, DfsPostOrder, EdgeFiltered, Walker},
 };
 
 use crate::*;
@@ -13,7 +14,15 @@ use crate::*;
 #[derive(Default)]
 pub struct ResourceGraph {
     name_map: HashMap<GraphNode, String>,
-    graph: DiGraphMap<GraphNode, f32>,
+    graph: DiGraphMap<GraphNode, Ratio>,
+    /// How each node gets made. Nodes that aren't listed are `NodeKind::Assembled`.
+    kinds: HashMap<GraphNode, NodeKind>,
+    /// The node each producer makes, keyed by the `TypeId` of the producer.
+    producer_nodes: HashMap<TypeId, GraphNode>,
+    /// What happened to each node during the run.
+    counters: HashMap<GraphNode, NodeCounters>,
+    /// Number of items that went along each edge during the run.
+    flows: HashMap<(GraphNode, GraphNode), Ratio>,
     /// Where to start the DFS when displaying the graph.
     graph_root: Option<GraphNode>,
 }
@@ -21,6 +30,83 @@ pub struct ResourceGraph {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct GraphNode(TypeId);
 
+/// How the item of a graph node gets made.
+#[derive(Clone)]
+pub enum NodeKind {
+    /// Put together from its inputs as soon as they're all there (`SingleMakeable`).
+    Assembled,
+    /// Made in bundles by a producer (`BundleMakeable`). The edges that aren't to the crafting
+    /// entity are the inputs to make one output.
+    Produced(ProducerInfo),
+    /// Made once from its inputs, then reused (`Available<T>`).
+    Unlock {
+        /// Whether the item is already available.
+        available: fn(&mut Resources) -> bool,
+        /// See `OnceMakeable::FROM_START`.
+        from_start: bool,
+        /// The `TypeId` of the `OnceMaker` that makes it.
+        maker: TypeId,
+    },
+}
+
+/// What happened to the item of a graph node during the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NodeCounters {
+    /// Number of items that got made.
+    pub produced: u64,
+    /// Number of items that went into making other items.
+    pub consumed: Ratio,
+    /// Number of sinks waiting on the producer of the item when we last looked.
+    pub waiting: usize,
+    /// Largest value `waiting` has had.
+    pub peak_waiting: usize,
+}
+
+impl std::fmt::Display for NodeCounters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "produced {}, consumed {}, waiting {} (peak {})",
+            self.produced, self.consumed, self.waiting, self.peak_waiting
+        )
+    }
+}
+
+/// Static information about the producer of a `NodeKind::Produced` node.
+#[derive(Clone)]
+pub struct ProducerInfo {
+    /// The `TypeId` of the producer, as used to key `Producers`.
+    pub producer: TypeId,
+    pub name: String,
+    pub craft_time: u64,
+    /// Number of items the producer makes at once.
+    pub output_amount: u32,
+    /// See `Producer::ALWAYS_BUSY`.
+    pub always_busy: bool,
+    /// See `Producer::FROM_START`.
+    pub from_start: bool,
+    /// What we build to scale up the producer. Not a node of the graph if there is nothing to
+    /// build, e.g. for `HandCrafter`.
+    pub entity: GraphNode,
+    /// How many of the item we have in stock.
+    pub stock: fn(&mut Resources) -> u32,
+}
+
+impl ProducerInfo {
+    pub fn of<P: SingleOutputProducer<Output: IsBundle>>(stock: fn(&mut Resources) -> u32) -> Self {
+        Self {
+            producer: TypeId::of::<P>(),
+            name: P::name(),
+            craft_time: P::CRAFT_TIME,
+            output_amount: <P as SingleOutputProducer>::Output::AMOUNT,
+            always_busy: P::ALWAYS_BUSY,
+            from_start: P::FROM_START,
+            entity: ResourceGraph::node_for::<P::CraftingEntity>(),
+            stock,
+        }
+    }
+}
+
 impl std::fmt::Display for ResourceGraph {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut first_col: Vec<_> = vec![];
@@ -31,7 +117,9 @@ impl std::fmt::Display for ResourceGraph {
         let topo_sort = DfsPostOrder::new(&self.graph, first_node)
             .iter(&self.graph)
             .collect_vec();
+        let mut counters = vec![];
         for id in topo_sort.into_iter().rev() {
+            counters.push(self.counters(id));
             // for id in self.graph.nodes() {
             first_col.push(self.name_map.get(&id).unwrap());
             rows.push(
@@ -40,12 +128,13 @@ impl std::fmt::Display for ResourceGraph {
                     .map(|tgt| {
                         let w = self.graph.edge_weight(id, tgt).unwrap();
                         let name = self.name_map.get(&tgt).unwrap();
-                        let w = if w.is_zero() {
-                            String::new()
+                        if w.is_zero() {
+                            name.clone()
+                        } else if self.has_counters() {
+                            format!("{w} {name} ({})", self.flow(id, tgt))
                         } else {
-                            format!("{w} ")
-                        };
-                        format!("{w}{name}")
+                            format!("{w} {name}")
+                        }
                     })
                     .collect_vec(),
             );
@@ -59,22 +148,100 @@ impl std::fmt::Display for ResourceGraph {
             .max()
             .unwrap();
 
-        for (first, row) in first_col.into_iter().zip(rows) {
+        for ((first, row), counters) in first_col.into_iter().zip(rows).zip(counters) {
             let row = row
                 .into_iter()
                 .map(|x| format!("{x:w$}", w = other_cols_width))
                 .format(" ");
             writeln!(f, "{first:w$}  takes:  {}", row, w = first_col_width)?;
+            if self.has_counters() {
+                writeln!(f, "{:w$}  {counters}", "", w = first_col_width)?;
+            }
         }
         Ok(())
     }
 }
 
 impl ResourceGraph {
-    fn node_for<T: Any>() -> GraphNode {
+    pub fn node_for<T: Any>() -> GraphNode {
         GraphNode(TypeId::of::<T>())
     }
 
+    pub fn nodes(&self) -> impl Iterator<Item = GraphNode> {
+        self.name_map.keys().copied()
+    }
+    pub fn contains(&self, node: GraphNode) -> bool {
+        self.name_map.contains_key(&node)
+    }
+    pub fn name(&self, node: GraphNode) -> &str {
+        &self.name_map[&node]
+    }
+    /// The direct requirements of a node, with their weights.
+    pub fn edges(&self, node: GraphNode) -> impl Iterator<Item = (GraphNode, Ratio)> {
+        self.graph.edges(node).map(|(_, tgt, w)| (tgt, *w))
+    }
+
+    pub fn kind(&self, node: GraphNode) -> NodeKind {
+        self.kinds
+            .get(&node)
+            .cloned()
+            .unwrap_or(NodeKind::Assembled)
+    }
+    pub fn set_kind(&mut self, node: GraphNode, kind: NodeKind) {
+        match &kind {
+            NodeKind::Assembled => {}
+            NodeKind::Produced(info) => {
+                self.producer_nodes.insert(info.producer, node);
+            }
+            NodeKind::Unlock { maker, .. } => {
+                self.producer_nodes.insert(*maker, node);
+            }
+        }
+        self.kinds.insert(node, kind);
+    }
+
+    /// The node a producer makes, and how many items it makes at once.
+    pub fn producer_node(&self, producer: TypeId) -> Option<(GraphNode, u32)> {
+        let node = *self.producer_nodes.get(&producer)?;
+        let amount = match self.kind(node) {
+            NodeKind::Produced(info) => info.output_amount,
+            _ => 1,
+        };
+        Some((node, amount))
+    }
+    pub fn counters(&self, node: GraphNode) -> NodeCounters {
+        self.counters.get(&node).copied().unwrap_or_default()
+    }
+    /// Number of items that went from `to` into making `from`s.
+    pub fn flow(&self, from: GraphNode, to: GraphNode) -> Ratio {
+        self.flows.get(&(from, to)).copied().unwrap_or_default()
+    }
+    /// Whether we recorded anything during the run.
+    pub fn has_counters(&self) -> bool {
+        !self.counters.is_empty()
+    }
+
+    /// Record that `amount` items of `node` got made.
+    pub fn record_production(&mut self, node: GraphNode, amount: u64) {
+        self.counters.entry(node).or_default().produced += amount;
+    }
+    /// Record that the inputs for `amount` items of `node` got used up. Crafting entities and
+    /// unlocks are reused, so they don't count.
+    pub fn record_consumption(&mut self, node: GraphNode, amount: u32) {
+        let inputs = self.edges(node).filter(|(_, w)| !w.is_zero()).collect_vec();
+        for (tgt, w) in inputs {
+            let flow = w * amount;
+            *self.flows.entry((node, tgt)).or_default() += flow;
+            self.counters.entry(tgt).or_default().consumed += flow;
+        }
+    }
+    /// Record the number of sinks waiting on the producer of `node`.
+    pub fn record_waiting(&mut self, node: GraphNode, waiting: usize) {
+        let counters = self.counters.entry(node).or_default();
+        counters.waiting = waiting;
+        counters.peak_waiting = counters.peak_waiting.max(waiting);
+    }
+
     /// Add the node to the graph, and returns its id if that was the first time we added that
     /// node.
     pub fn add_node<T: Any>(&mut self) -> Option<GraphNode> {
@@ -90,13 +257,229 @@ impl ResourceGraph {
 
     /// Add the node to the graph, and returns its id if that was the first time we added that
     /// node.
-    pub fn add_edge_to<T: Any>(&mut self, start: GraphNode, weight: f32) {
+    pub fn add_edge_to<T: Any>(&mut self, start: GraphNode, weight: Ratio) {
         let to = Self::node_for::<T>();
         self.graph.add_edge(start, to, weight);
     }
 
+    /// The direct requirements to make a `T`, as `(resource, quantity)` pairs. If `T` is a single
+    /// item, this lists its inputs; if it is a tuple, this lists its components.
+    pub fn requirements<T: Makeable>() -> Vec<(String, Ratio)> {
+        let (graph, root) = Self::rooted::<T>();
+        let mut start = root;
+        if let Some((tgt,)) = graph.graph.neighbors(root).collect_tuple()
+            && tgt == Self::node_for::<T>()
+        {
+            start = tgt;
+        }
+        graph
+            .graph
+            .neighbors(start)
+            .map(|tgt| {
+                let w = *graph.graph.edge_weight(start, tgt).unwrap();
+                (graph.name_map[&tgt].clone(), w)
+            })
+            .collect()
+    }
+
+    /// The graph of what it takes to make a `T`, hanging off a placeholder root node that needs
+    /// one `T`. Returns the graph and the root.
+    pub fn rooted<T: Makeable>() -> (Self, GraphNode) {
+        /// Placeholder node to hang the requirements off.
+        struct Root;
+        let mut graph = Self::default();
+        let root = graph.add_node::<Root>().unwrap();
+        T::add_edge_to_graph(&mut graph, root, Ratio::ONE);
+        (graph, root)
+    }
+
+    /// The nodes reachable from `starts` following only the edges accepted by `follow`, parents
+    /// before children. The followed edges must not form a cycle.
+    pub fn topo_sort(
+        &self,
+        starts: impl IntoIterator<Item = GraphNode>,
+        follow: impl Fn(GraphNode, Ratio) -> bool,
+    ) -> Vec<GraphNode> {
+        let graph = EdgeFiltered::from_fn(&self.graph, |(_, tgt, w)| follow(tgt, *w));
+        let mut post_order = vec![];
+        let mut dfs = DfsPostOrder::empty(&graph);
+        for start in starts {
+            dfs.move_to(start);
+            while let Some(node) = dfs.next(&graph) {
+                post_order.push(node);
+            }
+        }
+        post_order.reverse();
+        post_order
+    }
+
+    /// Whether the edge to `tgt` is an input that gets consumed, as opposed to the crafting
+    /// entity of a producer. Unlocks are consumed once, see `bill_of_materials`.
+    pub fn is_material_edge(&self, tgt: GraphNode, w: Ratio) -> bool {
+        !w.is_zero() || matches!(self.kind(tgt), NodeKind::Unlock { .. })
+    }
+
+    /// The groups of items that need each other as inputs, each in no particular order. Crafting
+    /// entities don't count: a furnace that takes iron to build is expected.
+    pub fn material_cycles(&self) -> Vec<Vec<GraphNode>> {
+        let materials =
+            EdgeFiltered::from_fn(&self.graph, |(_, tgt, w)| self.is_material_edge(tgt, *w));
+        tarjan_scc(&materials)
+            .into_iter()
+            .filter(|scc| match scc.as_slice() {
+                [node] => self.graph.edge_weight(*node, *node).is_some(),
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Everything that goes into making a `T` from scratch, `T` itself excluded. Quantities are
+    /// exact: a producer that makes 2 items at once counts as half a craft for 1 item. We count
+    /// one of each crafting entity involved, and unlocks are made once no matter how many items
+    /// need them.
+    pub fn bill_of_materials<T: Makeable>() -> BillOfMaterials {
+        let (graph, root) = Self::rooted::<T>();
+
+        // Crafting entities can need their own output (a furnace takes iron), so we find them on
+        // the whole graph. Without them, what's left is acyclic.
+        let machines = Dfs::new(&graph.graph, root)
+            .iter(&graph.graph)
+            .filter_map(|node| match graph.kind(node) {
+                NodeKind::Produced(info) if graph.contains(info.entity) => Some(info.entity),
+                _ => None,
+            })
+            .unique()
+            .collect_vec();
+        let starts = std::iter::once(root).chain(machines.iter().copied());
+        let mut needed: HashMap<GraphNode, Ratio> =
+            starts.clone().map(|start| (start, Ratio::ONE)).collect();
+        let topo_sort = graph.topo_sort(starts, |tgt, w| graph.is_material_edge(tgt, w));
+
+        let mut bom = BillOfMaterials::default();
+        // Parents come before children, so a node's total is complete when we reach it.
+        for node in topo_sort {
+            let is_unlock = matches!(graph.kind(node), NodeKind::Unlock { .. });
+            let amount = if is_unlock { Ratio::ONE } else { needed[&node] };
+            let mut inputs = graph
+                .edges(node)
+                .filter(|&(tgt, w)| graph.is_material_edge(tgt, w))
+                .peekable();
+            let is_raw = inputs.peek().is_none();
+            for (tgt, w) in inputs {
+                let w = if w.is_zero() { Ratio::ONE } else { w };
+                *needed.entry(tgt).or_default() += amount * w;
+            }
+
+            if node == root || node == Self::node_for::<T>() {
+                continue;
+            }
+            let name = graph.name(node).to_string();
+            if is_unlock {
+                bom.unlocks.push(name);
+            } else if machines.contains(&node) {
+                bom.machines.push(name);
+            } else if is_raw {
+                bom.raw.insert(name, amount);
+            } else {
+                bom.intermediate.insert(name, amount);
+            }
+        }
+        bom
+    }
+
+    /// Format the graph as a Graphviz digraph. Edges point from an item to what it takes to make
+    /// it, labelled with the quantity needed per item. Zero-weight edges are drawn dashed when
+    /// they point to the crafting entity of a producer, and dotted when they point to an
+    /// `Available<T>` unlock. If we recorded anything during the run, nodes also show their
+    /// counters and edges the number of items that went along them in parentheses.
+    pub fn to_dot(&self) -> String {
+        fn escape(s: &str) -> String {
+            s.replace('\\', "\\\\").replace('"', "\\\"")
+        }
+        fn quote(s: &str) -> String {
+            format!("\"{}\"", escape(s))
+        }
+        let mut out =
+            String::from("digraph ResourceGraph {\n    rankdir=LR;\n    node [shape=box];\n");
+        let nodes = self
+            .name_map
+            .iter()
+            .sorted_by(|(_, a), (_, b)| a.cmp(b))
+            .collect_vec();
+        for &(&node, name) in &nodes {
+            if self.has_counters() {
+                let counters = self.counters(node).to_string();
+                let label = format!("\"{}\\n{}\"", escape(name), escape(&counters));
+                out += &format!("    {} [label={label}];\n", quote(name));
+            } else {
+                out += &format!("    {};\n", quote(name));
+            }
+        }
+        for &(&node, name) in &nodes {
+            let edges = self
+                .edges(node)
+                .sorted_by(|(a, _), (b, _)| self.name_map[a].cmp(&self.name_map[b]));
+            for (tgt, w) in edges {
+                let attrs = if !w.is_zero() && self.has_counters() {
+                    format!("label=\"{w} ({})\"", self.flow(node, tgt))
+                } else if !w.is_zero() {
+                    format!("label=\"{w}\"")
+                } else if matches!(self.kind(tgt), NodeKind::Unlock { .. }) {
+                    "style=dotted".to_string()
+                } else {
+                    "style=dashed".to_string()
+                };
+                out += &format!(
+                    "    {} -> {} [{attrs}];\n",
+                    quote(name),
+                    quote(&self.name_map[&tgt])
+                );
+            }
+        }
+        out += "}\n";
+        out
+    }
+
     /// Set the node to use as root when displaying the graph.
     pub fn set_display_root<T: Any>(&mut self) {
         self.graph_root = Some(Self::node_for::<T>())
     }
 }
+
+/// What it takes to make an item from scratch, see `ResourceGraph::bill_of_materials`. Items
+/// are listed from the target down.
+#[derive(Default)]
+pub struct BillOfMaterials {
+    /// Items that nothing else goes into, e.g. ores, with the total amount needed.
+    pub raw: IndexMap<String, Ratio>,
+    /// Items made along the way, with the total amount needed.
+    pub intermediate: IndexMap<String, Ratio>,
+    /// The crafting entities we need one of.
+    pub machines: Vec<String>,
+    /// The `Available<T>` unlocks needed along the way.
+    pub unlocks: Vec<String>,
+}
+
+impl std::fmt::Display for BillOfMaterials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sections = [("raw", &self.raw), ("intermediate", &self.intermediate)];
+        for (title, items) in sections {
+            writeln!(f, "{title}:")?;
+            let rows = items
+                .iter()
+                .map(|(name, amount)| [format!("  {amount}  "), name.clone()])
+                .collect_vec();
+            if !rows.is_empty() {
+                writeln!(f, "{}", format_in_columns(&rows))?;
+            }
+        }
+        let sections = [("machines", &self.machines), ("unlocks", &self.unlocks)];
+        for (title, names) in sections {
+            writeln!(f, "{title}:")?;
+            for name in names {
+                writeln!(f, "  {name}")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::*;
+
+/// Where we read the build order from.
+pub const CONFIG_PATH: &str = "rustorio.toml";
+/// The checked-in config, whose build order we follow when there is no `CONFIG_PATH`.
+const DEFAULT_CONFIG: &str = include_str!("../../../rustorio.toml");
+/// The priority of the actions that don't specify one.
+pub const DEFAULT_BUILD_ORDER_PRIORITY: Priority = Priority(4);
+
+/// A `GameState` method that builds a producing entity, so that it can be called by name.
+pub struct BuildMethod {
+    /// The method, e.g. `add_furnace`.
+    pub method: &'static str,
+    /// Its type argument, e.g. `IronSmelting`.
+    pub arg: String,
+    /// The producer that gets the new entity, see `Producer::name`.
+    pub producer: String,
+    /// Whether the method goes through `GameState::scale_up`, see `Action::ScaleUp`.
+    pub scale_up: bool,
+    call: fn(&mut GameState, Priority) -> Option<WakeHandle<()>>,
+}
+
+impl BuildMethod {
+    fn new<P: Producer>(
+        method: &'static str,
+        arg: String,
+        scale_up: bool,
+        call: fn(&mut GameState, Priority) -> Option<WakeHandle<()>>,
+    ) -> Self {
+        Self {
+            method,
+            arg,
+            producer: P::name(),
+            scale_up,
+            call,
+        }
+    }
+
+    /// The action this method performs, as seen by the simulator.
+    pub fn action(&self, p: Priority) -> Action {
+        let producer = self.producer.clone();
+        if self.scale_up {
+            Action::ScaleUp { producer, p }
+        } else {
+            Action::AddMachine { producer, p }
+        }
+    }
+}
+
+/// Maps names to the generic `GameState` methods that build producing entities.
+pub struct BuildRegistry {
+    methods: Vec<BuildMethod>,
+}
+
+impl Default for BuildRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuildRegistry {
+    pub fn new() -> Self {
+        let mut this = Self { methods: vec![] };
+        this.register_miner::<IronOre>();
+        this.register_miner::<CopperOre>();
+        this.register_furnace::<IronSmelting>();
+        this.register_furnace::<CopperSmelting>();
+        this.register_furnace::<SteelSmelting>();
+        this.register_assembler::<CopperWireRecipe>();
+        this.register_assembler::<ElectronicCircuitRecipe>();
+        this.register_assembler::<PointRecipe>();
+        this.register_lab::<SteelTechnology>();
+        this.register_lab::<PointsTechnology>();
+        this
+    }
+
+    fn register_miner<Ore: OreType + Any>(&mut self) {
+        self.methods.push(BuildMethod::new::<Territory<Ore>>(
+            "add_miner",
+            type_name::<Ore>(),
+            true,
+            |state, p| {
+                state.add_miner::<Ore>(p);
+                None
+            },
+        ));
+    }
+    /// Registers both `add_machine::<M>` under `method` and `scale_up::<MultiMachine<M>>`.
+    fn register_machine<M: Machine + Makeable>(
+        &mut self,
+        method: &'static str,
+        arg: String,
+        call: fn(&mut GameState, Priority) -> Option<WakeHandle<()>>,
+    ) {
+        self.methods.push(BuildMethod::new::<MultiMachine<M>>(
+            method, arg, false, call,
+        ));
+        self.methods.push(BuildMethod::new::<MultiMachine<M>>(
+            "scale_up",
+            type_name::<MultiMachine<M>>(),
+            true,
+            |state, p| {
+                state.scale_up::<MultiMachine<M>>(p);
+                None
+            },
+        ));
+    }
+    fn register_furnace<R>(&mut self)
+    where
+        R: FurnaceRecipe + Any,
+        Furnace<R>: Machine + Makeable,
+    {
+        self.register_machine::<Furnace<R>>("add_furnace", type_name::<R>(), |state, p| {
+            Some(state.add_furnace::<R>(p))
+        });
+    }
+    fn register_assembler<R>(&mut self)
+    where
+        R: AssemblerRecipe + Any,
+        Assembler<R>: Machine + Makeable,
+    {
+        self.register_machine::<Assembler<R>>("add_assembler", type_name::<R>(), |state, p| {
+            Some(state.add_assembler::<R>(p))
+        });
+    }
+    fn register_lab<T>(&mut self)
+    where
+        T: Technology + Any,
+        Lab<T>: Machine + Makeable,
+    {
+        self.register_machine::<Lab<T>>("add_lab", type_name::<T>(), |state, p| {
+            Some(state.add_lab::<T>(p))
+        });
+    }
+
+    /// Look up a method by name and type argument.
+    pub fn get(&self, method: &str, arg: &str) -> Option<&BuildMethod> {
+        self.methods
+            .iter()
+            .find(|m| m.method == method && m.arg == arg)
+    }
+
+    /// The method that performs this action, if any.
+    pub fn for_action(&self, action: &Action) -> Option<&BuildMethod> {
+        let (producer, scale_up) = match action {
+            Action::ScaleUp { producer, .. } => (producer, true),
+            Action::AddMachine { producer, .. } => (producer, false),
+            Action::Complete(_) => return None,
+        };
+        self.methods
+            .iter()
+            .find(|m| m.producer == *producer && m.scale_up == scale_up)
+    }
+
+    /// Parse the `[build_order]` section of a `rustorio.toml`, which lists the opening moves of
+    /// `play` instead of hardcoding them. Returns `None` if there is no such section.
+    ///
+    /// ```toml
+    /// [build_order]
+    /// priority = 4
+    /// actions = [
+    ///     { scale_up = "MultiMachine<Furnace<IronSmelting>>" },
+    ///     { add_furnace = "IronSmelting" },
+    ///     { add_miner = "CopperOre", priority = 5 },
+    ///     { complete = "add_furnace::<IronSmelting>" },
+    ///     { complete = true },
+    /// ]
+    /// ```
+    ///
+    /// `complete` waits for the last machine added by the named action, or with `true` for all
+    /// those added since the previous `complete`.
+    pub fn parse(&self, config: &str) -> Result<Option<Vec<Action>>, BuildOrderError> {
+        let config: toml::Table = config.parse().map_err(BuildOrderError::Toml)?;
+        let Some(section) = config.get("build_order") else {
+            return Ok(None);
+        };
+        let invalid =
+            |index: Option<usize>, message: String| BuildOrderError::Invalid { index, message };
+        let priority = |value: Option<&toml::Value>, default: Priority, index| match value {
+            None => Ok(default),
+            Some(v) => v
+                .as_integer()
+                .and_then(|p| u16::try_from(p).ok())
+                .map(Priority)
+                .ok_or_else(|| invalid(index, format!("invalid priority `{v}`"))),
+        };
+        let default_priority =
+            priority(section.get("priority"), DEFAULT_BUILD_ORDER_PRIORITY, None)?;
+        // The producers of the `add_*` actions we could still wait for.
+        let mut pending = vec![];
+        let Some(entries) = section.get("actions").and_then(|a| a.as_array()) else {
+            return Err(invalid(None, "expected an `actions` array".to_string()));
+        };
+        entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let Some(entry) = entry.as_table() else {
+                    return Err(invalid(Some(i), "expected a table".to_string()));
+                };
+                let p = priority(entry.get("priority"), default_priority, Some(i))?;
+                let mut calls = entry.iter().filter(|(key, _)| *key != "priority");
+                let (Some((method, arg)), None) = (calls.next(), calls.next()) else {
+                    return Err(invalid(Some(i), "expected exactly one action".to_string()));
+                };
+                if method == "complete" {
+                    let producer = match (arg.as_bool(), arg.as_str()) {
+                        (Some(true), _) => None,
+                        (_, Some(call)) => Some(
+                            self.completed_producer(call, &pending)
+                                .map_err(|message| invalid(Some(i), message))?,
+                        ),
+                        _ => {
+                            return Err(invalid(
+                                Some(i),
+                                "expected `complete = true` or `complete = \"method::<Type>\"`"
+                                    .to_string(),
+                            ));
+                        }
+                    };
+                    take_completed(&mut pending, producer.as_ref());
+                    return Ok(Action::Complete(producer));
+                }
+                let Some(arg) = arg.as_str() else {
+                    return Err(invalid(
+                        Some(i),
+                        format!("expected a type name for `{method}`"),
+                    ));
+                };
+                let m = self.get(method, arg).ok_or_else(|| {
+                    invalid(Some(i), format!("unknown action `{method}::<{arg}>`"))
+                })?;
+                if !m.scale_up {
+                    pending.push((m.producer.clone(), ()));
+                }
+                Ok(m.action(p))
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    /// The producer that `complete = "method::<Type>"` waits for. There must be a handle for it
+    /// in `pending`.
+    fn completed_producer(&self, call: &str, pending: &[(String, ())]) -> Result<String, String> {
+        let parsed = call
+            .strip_suffix('>')
+            .and_then(|call| call.split_once("::<"));
+        let Some((method, arg)) = parsed else {
+            return Err(format!("expected `method::<Type>`, got `{call}`"));
+        };
+        let m = self
+            .get(method, arg)
+            .ok_or_else(|| format!("unknown action `{call}`"))?;
+        if m.scale_up {
+            return Err(format!("`{call}` has no handle to complete"));
+        }
+        if !pending.iter().any(|(producer, _)| *producer == m.producer) {
+            return Err(format!("no `{call}` to complete"));
+        }
+        Ok(m.producer.clone())
+    }
+
+    /// Format a build order as a `[build_order]` section that `parse` reads back.
+    pub fn to_toml(&self, actions: &[Action], default_priority: Priority) -> String {
+        let entries = actions.iter().map(|action| match action {
+            Action::Complete(None) => "{ complete = true }".to_string(),
+            Action::Complete(Some(producer)) => {
+                let add = Action::AddMachine {
+                    producer: producer.clone(),
+                    p: default_priority,
+                };
+                let m = self.for_action(&add).unwrap();
+                format!("{{ complete = \"{}::<{}>\" }}", m.method, m.arg)
+            }
+            Action::ScaleUp { p, .. } | Action::AddMachine { p, .. } => {
+                let m = self.for_action(action).unwrap();
+                let priority = if *p == default_priority {
+                    String::new()
+                } else {
+                    format!(", priority = {}", p.0)
+                };
+                format!("{{ {} = \"{}\"{priority} }}", m.method, m.arg)
+            }
+        });
+        let entries: String = entries.map(|e| format!("    {e},\n")).collect();
+        format!(
+            "[build_order]\npriority = {}\nactions = [\n{entries}]\n",
+            default_priority.0
+        )
+    }
+}
+
+/// Why we couldn't read the build order.
+#[derive(Debug)]
+pub enum BuildOrderError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    /// The entry at `index` of `actions` (or the section itself if `None`) doesn't make sense.
+    Invalid {
+        index: Option<usize>,
+        message: String,
+    },
+}
+
+impl fmt::Display for BuildOrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildOrderError::Io(e) => write!(f, "{e}"),
+            BuildOrderError::Toml(e) => write!(f, "{e}"),
+            BuildOrderError::Invalid {
+                index: Some(i),
+                message,
+            } => write!(f, "`build_order.actions[{i}]`: {message}"),
+            BuildOrderError::Invalid {
+                index: None,
+                message,
+            } => write!(f, "`build_order`: {message}"),
+        }
+    }
+}
+
+/// Read the build order from the `[build_order]` section of the file at `path`. A missing section
+/// gives an empty build order, a missing file the checked-in build order.
+pub fn load_build_order(path: impl AsRef<Path>) -> Result<Vec<Action>, BuildOrderError> {
+    let config = match fs::read_to_string(path) {
+        Ok(config) => config,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => DEFAULT_CONFIG.to_string(),
+        Err(e) => return Err(BuildOrderError::Io(e)),
+    };
+    Ok(BuildRegistry::new().parse(&config)?.unwrap_or_default())
+}
+
+impl GameState {
+    /// Perform the actions in order. Each `Action::Complete` waits for the machines it names, see
+    /// `take_completed`.
+    pub fn run_build_order(&mut self, actions: &[Action]) {
+        let registry = BuildRegistry::new();
+        let mut pending = vec![];
+        for action in actions {
+            match action {
+                Action::ScaleUp { p, .. } | Action::AddMachine { p, .. } => {
+                    let m = registry
+                        .for_action(action)
+                        .unwrap_or_else(|| panic!("no method for {action:?}"));
+                    if let Some(h) = (m.call)(self, *p) {
+                        pending.push((m.producer.clone(), h));
+                    }
+                }
+                Action::Complete(producer) => {
+                    let handles = take_completed(&mut pending, producer.as_ref());
+                    let all = WakeHandle::join_all(self, handles);
+                    self.complete(all);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_toml_round_trips() {
+        let registry = BuildRegistry::new();
+        let config = r#"
+            [build_order]
+            priority = 3
+            actions = [
+                { scale_up = "MultiMachine<Furnace<IronSmelting>>" },
+                { add_furnace = "IronSmelting" },
+                { add_miner = "CopperOre", priority = 5 },
+                { add_assembler = "CopperWireRecipe" },
+                { complete = "add_furnace::<IronSmelting>" },
+                { complete = true },
+            ]
+        "#;
+        let actions = registry.parse(config).unwrap().unwrap();
+        assert_eq!(actions.len(), 6);
+        assert!(matches!(
+            &actions[2],
+            Action::ScaleUp { p: Priority(5), .. }
+        ));
+        assert!(matches!(
+            &actions[3],
+            Action::AddMachine { p: Priority(3), .. }
+        ));
+        let toml = registry.to_toml(&actions, Priority(3));
+        assert_eq!(registry.parse(&toml).unwrap().unwrap(), actions);
+    }
+
+    #[test]
+    fn the_default_config_parses() {
+        let actions = BuildRegistry::new().parse(DEFAULT_CONFIG).unwrap();
+        assert!(actions.is_some());
+    }
+
+    #[test]
+    fn invalid_actions_are_reported() {
+        let registry = BuildRegistry::new();
+        let error = |actions: &str| {
+            let config = format!("[build_order]\nactions = [{actions}]");
+            registry.parse(&config).unwrap_err().to_string()
+        };
+        assert_eq!(
+            error(r#"{ add_furnace = "Iron" }"#),
+            "`build_order.actions[0]`: unknown action `add_furnace::<Iron>`"
+        );
+        assert_eq!(
+            error(r#"{ complete = "add_furnace::<IronSmelting>" }"#),
+            "`build_order.actions[0]`: no `add_furnace::<IronSmelting>` to complete"
+        );
+        assert_eq!(
+            error(r#"{ add_miner = "IronOre" }, { complete = "add_miner::<IronOre>" }"#),
+            "`build_order.actions[1]`: `add_miner::<IronOre>` has no handle to complete"
+        );
+        assert_eq!(
+            error(r#"{ add_furnace = "IronSmelting", priority = -1 }"#),
+            "`build_order.actions[0]`: invalid priority `-1`"
+        );
+    }
+}
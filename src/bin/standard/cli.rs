@@ -0,0 +1,127 @@
+use std::sync::{
+    OnceLock,
+    atomic::{AtomicU8, Ordering},
+};
+
+use clap::{Parser, ValueEnum};
+
+use crate::*;
+
+/// Plays Rustorio by itself.
+#[derive(Parser)]
+pub struct Cli {
+    /// What to do.
+    #[arg(value_enum, default_value_t = Strategy::Play)]
+    pub strategy: Strategy,
+    /// Number of ticks between two load reports.
+    #[arg(long, default_value_t = DEFAULT_REPORT_PERIOD, value_parser = clap::value_parser!(u64).range(1..))]
+    pub report_period: u64,
+    /// Number of ticks between two metrics samples.
+    #[arg(long, default_value_t = DEFAULT_METRICS_PERIOD, value_parser = clap::value_parser!(u64).range(1..))]
+    pub metrics_period: u64,
+    /// Where load reports go.
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub report: ReportFormat,
+    /// Number of ticks `complete` may spend waiting on a single handle.
+    #[arg(long, default_value_t = DEFAULT_TICK_BUDGET)]
+    pub tick_budget: u64,
+    /// Number of ticks without any progress after which we give up and report a deadlock.
+    #[arg(long, default_value_t = DEFAULT_STALL_TICKS, value_parser = clap::value_parser!(u64).range(1..))]
+    pub stall_ticks: u64,
+    /// Number of ticks after which a waiter gains one priority level, so that low-priority
+    /// waiters don't starve. `0`, the default, disables aging.
+    #[arg(long, default_value_t = 0)]
+    pub ticks_per_level: u64,
+    /// How to print the resource graph at the end of the game.
+    #[arg(long, value_enum, default_value_t = GraphFormat::Table)]
+    pub graph_format: GraphFormat,
+    /// Which progress messages to print.
+    #[arg(long, value_enum, default_value_t = Verbosity::All)]
+    pub verbosity: Verbosity,
+    /// Number of `Point`s per tick for the `rates` strategy, e.g. `3` or `1/10`.
+    #[arg(long, default_value = "1/10")]
+    pub rate: Ratio,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Strategy {
+    /// Play the game, following the build order in `rustorio.toml`.
+    Play,
+    /// Predict how the build order in `rustorio.toml` would go, without playing.
+    Simulate,
+    /// Search for a good build order, without playing.
+    Search,
+    /// Print everything it takes to make `Victory` from scratch, without playing.
+    Bom,
+    /// Compute how many machines it takes to make `Point`s at the given `--rate`, without
+    /// playing.
+    Rates,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ReportFormat {
+    /// A table on stderr, see `TextReporter`.
+    Text,
+    /// One JSON object per line on stderr, see `JsonLinesReporter`.
+    Json,
+    None,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum GraphFormat {
+    /// One line per item with what it takes to make it, see the `Display` impl of
+    /// `ResourceGraph`.
+    Table,
+    /// A Graphviz digraph, see `ResourceGraph::to_dot`.
+    Dot,
+    None,
+}
+
+/// Which progress messages to print. Each level includes the previous ones.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Verbosity {
+    Quiet,
+    /// Machines and unlocks being built.
+    Machines,
+    /// Also every craft by hand.
+    All,
+}
+
+static CLI: OnceLock<Cli> = OnceLock::new();
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::All as u8);
+
+impl Cli {
+    /// Parse the command line. Exits on error or `--help`.
+    pub fn init() {
+        let cli = Cli::parse();
+        VERBOSITY.store(cli.verbosity as u8, Ordering::Relaxed);
+        let _ = CLI.set(cli);
+    }
+
+    /// The parsed command line.
+    pub fn get() -> &'static Cli {
+        CLI.get().expect("`Cli::init` wasn't called")
+    }
+
+    /// Apply the options that tune the runtime.
+    pub fn configure(&self, state: &mut GameState) {
+        state.report_period = self.report_period;
+        state.tick_budget = self.tick_budget;
+        state.stall_ticks = self.stall_ticks;
+        state.metrics = Metrics::new(self.metrics_period);
+        state.set_aging(Aging {
+            ticks_per_level: Some(self.ticks_per_level).filter(|&k| k != 0),
+            ..state.producers.aging
+        });
+        state.reporter = match self.report {
+            ReportFormat::Text => Box::new(TextReporter),
+            ReportFormat::Json => Box::new(JsonLinesReporter::stderr()),
+            ReportFormat::None => Box::new(SilentReporter),
+        };
+    }
+}
+
+/// Whether to print messages of that level.
+pub fn verbose(level: Verbosity) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= level as u8
+}
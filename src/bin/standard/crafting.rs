@@ -9,10 +9,14 @@ pub trait Makeable: Any + Sized {
     /// Record these resources in the global resource graph.
     fn add_nodes_to_graph(graph: &mut ResourceGraph);
     /// Record an edge to these resources in the global resource graph.
-    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: f32);
+    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: Ratio);
 
     /// Estimated time to produce this item.
-    fn production_time(state: &mut GameState) -> f32;
+    fn production_time(state: &mut GameState) -> f32 {
+        Self::critical_path(state).total()
+    }
+    /// The chain of items that dominates `production_time`.
+    fn critical_path(state: &mut GameState) -> CriticalPath;
 }
 impl Makeable for () {
     fn make_to(state: &mut GameState, _p: Priority, sink: StateSink<Self>) {
@@ -20,10 +24,10 @@ impl Makeable for () {
     }
 
     fn add_nodes_to_graph(_graph: &mut ResourceGraph) {}
-    fn add_edge_to_graph(_graph: &mut ResourceGraph, _start: GraphNode, _weight: f32) {}
+    fn add_edge_to_graph(_graph: &mut ResourceGraph, _start: GraphNode, _weight: Ratio) {}
 
-    fn production_time(_state: &mut GameState) -> f32 {
-        0.
+    fn critical_path(_state: &mut GameState) -> CriticalPath {
+        CriticalPath::default()
     }
 }
 impl<A: Makeable> Makeable for (A,) {
@@ -34,94 +38,97 @@ impl<A: Makeable> Makeable for (A,) {
     fn add_nodes_to_graph(graph: &mut ResourceGraph) {
         A::add_nodes_to_graph(graph);
     }
-    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: f32) {
-        A::add_edge_to_graph(graph, start, weight);
-    }
-
-    fn production_time(state: &mut GameState) -> f32 {
-        A::production_time(state)
-    }
-}
-impl<A: Makeable, B: Makeable> Makeable for (A, B) {
-    fn make_to(state: &mut GameState, p: Priority, sink: StateSink<Self>) {
-        let (a, b) = sink.split();
-        A::make_to(state, p, a);
-        B::make_to(state, p, b);
-    }
-
-    fn add_nodes_to_graph(graph: &mut ResourceGraph) {
-        A::add_nodes_to_graph(graph);
-        B::add_nodes_to_graph(graph);
-    }
-    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: f32) {
+    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: Ratio) {
         A::add_edge_to_graph(graph, start, weight);
-        B::add_edge_to_graph(graph, start, weight);
     }
 
-    fn production_time(state: &mut GameState) -> f32 {
-        A::production_time(state).max(B::production_time(state))
+    fn critical_path(state: &mut GameState) -> CriticalPath {
+        A::critical_path(state)
     }
 }
-impl<A: Makeable, B: Makeable, C: Makeable> Makeable for (A, B, C) {
-    fn make_to(state: &mut GameState, p: Priority, sink: StateSink<Self>) {
-        let sink = sink.map(|_, ((x, y), z)| (x, y, z));
-        let (ab, c) = sink.split();
-        let (a, b) = ab.split();
-        A::make_to(state, p, a);
-        B::make_to(state, p, b);
-        C::make_to(state, p, c);
-    }
+macro_rules! impl_makeable_tuple {
+    ($($T:ident $x:ident),+) => {
+        impl<$($T: Makeable),+> Makeable for ($($T,)+) {
+            fn make_to(state: &mut GameState, p: Priority, sink: StateSink<Self>) {
+                let ($($x,)+) = sink.split();
+                $(state.make_to(p, $x);)+
+            }
 
-    fn add_nodes_to_graph(graph: &mut ResourceGraph) {
-        A::add_nodes_to_graph(graph);
-        B::add_nodes_to_graph(graph);
-        C::add_nodes_to_graph(graph);
-    }
-    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: f32) {
-        A::add_edge_to_graph(graph, start, weight);
-        B::add_edge_to_graph(graph, start, weight);
-        C::add_edge_to_graph(graph, start, weight);
-    }
+            fn add_nodes_to_graph(graph: &mut ResourceGraph) {
+                $($T::add_nodes_to_graph(graph);)+
+            }
+            fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: Ratio) {
+                $($T::add_edge_to_graph(graph, start, weight);)+
+            }
 
-    fn production_time(state: &mut GameState) -> f32 {
-        A::production_time(state)
-            .max(B::production_time(state))
-            .max(C::production_time(state))
-    }
+            fn critical_path(state: &mut GameState) -> CriticalPath {
+                CriticalPath::slowest([$($T::critical_path(state)),+])
+            }
+        }
+    };
 }
+impl_makeable_tuple!(A a, B b);
+impl_makeable_tuple!(A a, B b, C c);
+impl_makeable_tuple!(A a, B b, C c, D d);
+impl_makeable_tuple!(A a, B b, C c, D d, E e);
+impl_makeable_tuple!(A a, B b, C c, D d, E e, F f);
+impl_makeable_tuple!(A a, B b, C c, D d, E e, F f, G g);
+impl_makeable_tuple!(A a, B b, C c, D d, E e, F f, G g, H h);
 impl<const N: usize, T: Makeable> Makeable for [T; N] {
     fn make_to(state: &mut GameState, p: Priority, sink: StateSink<Self>) {
         for sink in sink.split_n() {
-            T::make_to(state, p, sink);
+            state.make_to(p, sink);
         }
     }
 
     fn add_nodes_to_graph(graph: &mut ResourceGraph) {
         T::add_nodes_to_graph(graph);
     }
-    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: f32) {
-        T::add_edge_to_graph(graph, start, weight * N as f32);
+    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: Ratio) {
+        T::add_edge_to_graph(graph, start, weight * N as u32);
     }
 
-    fn production_time(state: &mut GameState) -> f32 {
-        T::production_time(state) * N as f32
+    fn critical_path(state: &mut GameState) -> CriticalPath {
+        T::critical_path(state).times(N as u32)
     }
 }
 
-impl<const AMOUNT: u32, R: BundleMakeable> Makeable for Bundle<R, AMOUNT>
-where
-    [(); (AMOUNT / <R::Producer as SingleOutputProducer>::Output::AMOUNT) as usize]:,
-{
+impl<const AMOUNT: u32, R: BundleMakeable> Makeable for Bundle<R, AMOUNT> {
     fn make_to(state: &mut GameState, p: Priority, sink: StateSink<Self>) {
-        if let Ok(x) = state.resources.resource().bundle() {
+        if let Some(x) = state.resources.pool().bundle() {
             sink.give(state, x);
             return;
         }
-        // We cleverly don't fetch the whole input at once. Instead, as soon as the first input
-        // bundle arrives we feed it to the producer.
-        // Split the sink into individual chunks that match what the produces produces.
-        for sink in sink.split_resource() {
-            let sink = sink.map::<<R::Producer as Producer>::Output>(|_, out| out.0);
+        // Wait in the pool for our share.
+        let (task, priority) = state.current_waiter(p);
+        let sink = state
+            .make_stateless(sink)
+            .map(|_, mut res: Resource<R>| res.bundle().unwrap());
+        let waiter = Waiter {
+            sink,
+            task,
+            priority,
+            since: state.tick.cur(),
+        };
+        let aging = state.producers.aging;
+        state
+            .resources
+            .pool()
+            .wait(&mut state.queue, &state.tasks, aging, AMOUNT, waiter);
+        // Then make enough for ourselves. Each output goes to the pool rather than to us: if some
+        // stock turns up in the meantime (salvaged resources, leftovers), it serves us or a more
+        // urgent waiter sooner, and what we make goes to whoever is next in line.
+        let chunk = <R::Producer as SingleOutputProducer>::Output::AMOUNT;
+        for _ in 0..AMOUNT.div_ceil(chunk) {
+            let sink = StateSink::from_fn(
+                |state: &mut GameState, out: <R::Producer as Producer>::Output| {
+                    let resource = out.0.to_resource();
+                    state
+                        .resources
+                        .pool()
+                        .add(&mut state.queue, &state.tasks, resource);
+                },
+            );
             state.produce_to_state_sink::<R::Producer>(p, sink);
         }
     }
@@ -129,12 +136,12 @@ where
     fn add_nodes_to_graph(graph: &mut ResourceGraph) {
         <R as BundleMakeable>::add_node_to_graph(graph);
     }
-    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: f32) {
-        <R as BundleMakeable>::add_edge_to_graph(graph, start, weight * AMOUNT as f32);
+    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: Ratio) {
+        <R as BundleMakeable>::add_edge_to_graph(graph, start, weight * AMOUNT);
     }
 
-    fn production_time(state: &mut GameState) -> f32 {
-        <R as BundleMakeable>::production_time(state) * AMOUNT as f32
+    fn critical_path(state: &mut GameState) -> CriticalPath {
+        <R as BundleMakeable>::critical_path(state).times(AMOUNT)
     }
 }
 
@@ -168,27 +175,42 @@ mod bundle_makeable {
         /// Record this resource in the global resource graph.
         fn add_node_to_graph(graph: &mut ResourceGraph) {
             if let Some(id) = graph.add_node::<Self>() {
-                let weight = 1f32 / <Self::Producer as SingleOutputProducer>::Output::AMOUNT as f32;
-                <<Self::Producer as Producer>::CraftingEntity>::add_edge_to_graph(graph, id, 0.);
+                let info = ProducerInfo::of::<Self::Producer>(|r| r.resource::<Self>().amount());
+                graph.set_kind(id, NodeKind::Produced(info));
+                let weight = Ratio::new(
+                    1,
+                    <Self::Producer as SingleOutputProducer>::Output::AMOUNT as u64,
+                );
+                <<Self::Producer as Producer>::CraftingEntity>::add_edge_to_graph(
+                    graph,
+                    id,
+                    Ratio::ZERO,
+                );
                 <<Self::Producer as Producer>::Input as Makeable>::add_edge_to_graph(
                     graph, id, weight,
                 );
             }
         }
         /// Record an edge to this resource in the global resource graph.
-        fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: f32) {
+        fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: Ratio) {
             Self::add_node_to_graph(graph);
             graph.add_edge_to::<Self>(start, weight);
         }
 
-        fn production_time(state: &mut GameState) -> f32 {
-            let input_time =
-                <<Self::Producer as Producer>::Input as Makeable>::production_time(state);
+        /// The path to make one item: its input, then the time per item on its producer.
+        fn critical_path(state: &mut GameState) -> CriticalPath {
+            let input_path =
+                <<Self::Producer as Producer>::Input as Makeable>::critical_path(state);
             let producer = &mut state.producer::<Self::Producer>().producer;
             let output_bundle_size = <Self::Producer as SingleOutputProducer>::Output::AMOUNT;
             let craft_time = producer.craft_time() as f32
                 / (output_bundle_size as f32 * producer.available_parallelism() as f32);
-            input_time + craft_time
+            input_path.after(CriticalStep {
+                item: type_name::<Self>(),
+                count: 1,
+                producer: Some(Self::Producer::name()),
+                time: craft_time,
+            })
         }
     }
 
@@ -246,12 +268,12 @@ impl<R: SingleMakeable> Makeable for R {
     fn add_nodes_to_graph(graph: &mut ResourceGraph) {
         <Self as SingleMakeable>::add_node_to_graph(graph);
     }
-    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: f32) {
+    fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: Ratio) {
         <Self as SingleMakeable>::add_edge_to_graph(graph, start, weight);
     }
 
-    fn production_time(state: &mut GameState) -> f32 {
-        <Self as SingleMakeable>::production_time(state)
+    fn critical_path(state: &mut GameState) -> CriticalPath {
+        <Self as SingleMakeable>::critical_path(state)
     }
 }
 
@@ -264,7 +286,15 @@ mod single_makeable {
         type Input: Makeable;
 
         fn make_to(state: &mut GameState, p: Priority, sink: StateSink<Self>) {
-            state.make_to(p, sink.map(Self::make_from_input));
+            state.make_to(
+                p,
+                sink.map(|state, input| {
+                    let node = ResourceGraph::node_for::<Self>();
+                    state.graph.record_consumption(node, 1);
+                    state.graph.record_production(node, 1);
+                    Self::make_from_input(state, input)
+                }),
+            );
         }
 
         fn make_from_input(state: &mut GameState, input: Self::Input) -> Self;
@@ -272,18 +302,23 @@ mod single_makeable {
         /// Record this resource in the global resource graph.
         fn add_node_to_graph(graph: &mut ResourceGraph) {
             if let Some(id) = graph.add_node::<Self>() {
-                Self::Input::add_edge_to_graph(graph, id, 1f32);
+                Self::Input::add_edge_to_graph(graph, id, Ratio::ONE);
             }
         }
         /// Record an edge to this resource in the global resource graph.
-        fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: f32) {
+        fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, weight: Ratio) {
             Self::add_node_to_graph(graph);
             graph.add_edge_to::<Self>(start, weight);
         }
 
-        /// Time to produce this item.
-        fn production_time(state: &mut GameState) -> f32 {
-            <Self::Input as Makeable>::production_time(state)
+        /// The path to make this item. Putting it together takes no time.
+        fn critical_path(state: &mut GameState) -> CriticalPath {
+            <Self::Input as Makeable>::critical_path(state).after(CriticalStep {
+                item: type_name::<Self>(),
+                count: 1,
+                producer: None,
+                time: 0.,
+            })
         }
     }
 
@@ -343,17 +378,39 @@ mod single_makeable {
             unreachable!()
         }
 
+        fn add_node_to_graph(graph: &mut ResourceGraph) {
+            if let Some(id) = graph.add_node::<Self>() {
+                let available = |r: &mut Resources| r.reusable::<T>().available().is_some();
+                let from_start = T::FROM_START;
+                let maker = TypeId::of::<OnceMaker<T>>();
+                graph.set_kind(
+                    id,
+                    NodeKind::Unlock {
+                        available,
+                        from_start,
+                        maker,
+                    },
+                );
+                Self::Input::add_edge_to_graph(graph, id, Ratio::ONE);
+            }
+        }
         // Override the weight to make the graph prettier.
-        fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, _weight: f32) {
+        fn add_edge_to_graph(graph: &mut ResourceGraph, start: GraphNode, _weight: Ratio) {
             Self::add_node_to_graph(graph);
-            graph.add_edge_to::<Self>(start, 0.);
+            graph.add_edge_to::<Self>(start, Ratio::ZERO);
         }
 
-        fn production_time(state: &mut GameState) -> f32 {
-            match state.resources.reusable::<T>().available() {
-                Some(_) => 0.,
-                None => <Self::Input as Makeable>::production_time(state),
-            }
+        fn critical_path(state: &mut GameState) -> CriticalPath {
+            let input_path = match state.resources.reusable::<T>().available() {
+                Some(_) => CriticalPath::default(),
+                None => <Self::Input as Makeable>::critical_path(state),
+            };
+            input_path.after(CriticalStep {
+                item: type_name::<Self>(),
+                count: 1,
+                producer: None,
+                time: 0.,
+            })
         }
     }
 
@@ -364,6 +421,8 @@ mod single_makeable {
         /// Items that can be made once and reused.
         pub trait OnceMakeable: Sized + Reusable + Any {
             type Input: Makeable;
+            /// Whether the item is only available if we start with it.
+            const FROM_START: bool = false;
 
             fn trigger_make(state: &mut GameState, p: Priority, sink: StateSink<Available<Self>>) {
                 state.produce_to_state_sink::<OnceMaker<Self>>(p, sink);
@@ -374,6 +433,7 @@ mod single_makeable {
 
         impl OnceMakeable for SteelTechnology {
             type Input = ();
+            const FROM_START: bool = true;
 
             fn make_from_input(_state: &mut GameState, _input: Self::Input) -> Self {
                 unreachable!("available from the start")
@@ -397,7 +457,9 @@ mod single_makeable {
                 let lab = pqw
                     .producer
                     .take_map(|lab| lab.change_technology(&points_tech).unwrap());
-                println!("changing the labs to `PointsTechnology`");
+                if verbose(Verbosity::Machines) {
+                    println!("changing the labs to `PointsTechnology`");
+                }
                 *state.producers.machine() = ProducerWithQueue::new(lab);
                 state.resources.reusable().set(points_tech);
                 steel_smelting
@@ -474,12 +536,27 @@ mod single_makeable {
 impl GameState {
     pub fn make<T: Makeable>(&mut self, p: Priority) -> WakeHandle<T> {
         let (h, sink) = WakeHandle::make_pipe();
-        self.make_to(p, sink);
+        let id = self.make_to(p, sink);
+        h.set_task(id);
         h
     }
-    pub fn make_to<T: Makeable>(&mut self, p: Priority, sink: StateSink<T>) {
+    /// Request a `T`, recorded as a task in `self.tasks`. Returns the id of that task.
+    pub fn make_to<T: Makeable>(&mut self, p: Priority, sink: StateSink<T>) -> TaskId {
         T::add_nodes_to_graph(&mut self.graph);
-        T::make_to(self, p, sink)
+        let id = self.tasks.start(type_name::<T>(), self.tick.cur(), p);
+        let p = self.tasks.get(id).priority.get();
+        let sink = StateSink::from_fn(move |state: &mut GameState, x: T| {
+            if state.tasks.is_cancelled(id) {
+                // Nobody wants this anymore.
+                x.salvage();
+                state.restock_salvaged();
+            } else {
+                state.tasks.finish(id, state.tick.cur());
+                sink.give(state, x);
+            }
+        });
+        self.in_task(id, |state| T::make_to(state, p, sink));
+        id
     }
 
     pub fn producer<P: Producer>(&mut self) -> &mut ProducerWithQueue<P> {
@@ -493,15 +570,37 @@ impl GameState {
         p: Priority,
         sink: Sink<P::Output>,
     ) {
-        self.make_to(p, P::feed(p, sink));
+        let (task, priority) = self.current_waiter(p);
+        let p = priority.get();
+        self.make_to(p, P::feed(task, priority, sink));
+    }
+    /// The task and priority for a waiter enqueued by the current task. The waiter shares the
+    /// priority of the task, so that it follows any bump.
+    pub fn current_waiter(&self, p: Priority) -> (Option<TaskId>, SharedPriority) {
+        let task = self.tasks.current();
+        let priority = match task {
+            Some(id) => self.tasks.get(id).priority.clone(),
+            None => SharedPriority::new(p),
+        };
+        (task, priority)
     }
     pub fn produce_to_state_sink<P: Producer<Input: Makeable>>(
         &mut self,
         p: Priority,
         sink: StateSink<P::Output>,
     ) {
+        let id = self.tasks.start(P::name(), self.tick.cur(), p);
+        let sink = StateSink::from_fn(move |state: &mut GameState, x: P::Output| {
+            if state.tasks.is_cancelled(id) {
+                x.salvage();
+                state.restock_salvaged();
+            } else {
+                state.tasks.finish(id, state.tick.cur());
+                sink.give(state, x);
+            }
+        });
         // Does the conversion between sink types via the `CallBackQueue`.
         let sink = self.make_stateless(sink);
-        self.produce_to_sink::<P>(p, sink);
+        self.in_task(id, |state| state.produce_to_sink::<P>(p, sink));
     }
 }
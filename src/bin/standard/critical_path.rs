@@ -0,0 +1,89 @@
+use itertools::Itertools;
+
+use crate::*;
+
+/// One item along a `CriticalPath`.
+#[derive(Clone)]
+pub struct CriticalStep {
+    pub item: String,
+    /// How many of the item one target takes.
+    pub count: u64,
+    /// The producer that makes the item. `None` if it's put together from its inputs.
+    pub producer: Option<String>,
+    /// Time this step adds to the estimate.
+    pub time: f32,
+}
+
+/// The chain of items that dominates `Makeable::production_time`: at each tuple we follow the
+/// slowest component. Steps go from the target down to the first thing to make.
+#[derive(Clone, Default)]
+pub struct CriticalPath {
+    pub steps: Vec<CriticalStep>,
+}
+
+impl CriticalPath {
+    /// The estimated time to make the target, i.e. `Makeable::production_time`.
+    pub fn total(&self) -> f32 {
+        self.steps.iter().map(|s| s.time).sum()
+    }
+
+    /// Put a step in front of the path.
+    pub fn after(mut self, step: CriticalStep) -> Self {
+        self.steps.insert(0, step);
+        self
+    }
+
+    /// The path to make `n` targets one after the other.
+    pub fn times(mut self, n: u32) -> Self {
+        for step in &mut self.steps {
+            step.count *= n as u64;
+            step.time *= n as f32;
+        }
+        self
+    }
+
+    /// The slowest of the paths. The first one wins ties.
+    pub fn slowest(paths: impl IntoIterator<Item = Self>) -> Self {
+        paths
+            .into_iter()
+            .reduce(|a, b| if b.total() > a.total() { b } else { a })
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for CriticalPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.total();
+        writeln!(f, "critical path: {total:.1} ticks")?;
+        let rows = self
+            .steps
+            .iter()
+            .map(|s| {
+                // A producer without entities takes forever.
+                let share = if total.is_finite() && total > 0. {
+                    format!("{:.0}%", s.time / total * 100.)
+                } else {
+                    "-".to_string()
+                };
+                [
+                    format!("  {} {}  ", s.count, s.item),
+                    format!("{}  ", s.producer.as_deref().unwrap_or("-")),
+                    format!("{:.1}  ", s.time),
+                    share,
+                ]
+            })
+            .collect_vec();
+        if !rows.is_empty() {
+            writeln!(f, "{}", format_in_columns(&rows))?;
+        }
+        Ok(())
+    }
+}
+
+impl GameState {
+    /// The chain of items that dominates the estimated time to make a `T` with the producers we
+    /// have now.
+    pub fn critical_path<T: Makeable>(&mut self) -> CriticalPath {
+        T::critical_path(self)
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    ops::ControlFlow,
+};
+
+use itertools::Itertools;
+
+use crate::*;
+
+/// A producer that was being waited on when the game stopped making progress.
+pub struct StalledProducer {
+    pub name: String,
+    /// Number of waiters in the queue.
+    pub load: usize,
+    pub parallelism: u32,
+    /// Number of crafting entities we're in the process of building.
+    pub scaling_up: u32,
+    /// Whether we craft by hand when there is no crafting entity.
+    pub hand_fallback: bool,
+    /// The entity built when scaling up.
+    pub crafting_entity: String,
+    /// What building one crafting entity takes.
+    pub crafting_entity_needs: Vec<(String, Ratio)>,
+    /// If we're waiting for a scale up, the stalled producers that make what the crafting entity
+    /// needs.
+    pub blocked_on: Vec<String>,
+}
+
+impl StalledProducer {
+    /// Nothing will ever come out of this producer: it has no crafting entity, none is being built
+    /// and we can't craft by hand.
+    pub fn is_dead(&self) -> bool {
+        self.parallelism == 0 && self.scaling_up == 0 && !self.hand_fallback
+    }
+    /// We're waiting for a crafting entity to be built before anything can come out.
+    pub fn is_waiting_for_scale_up(&self) -> bool {
+        self.parallelism == 0 && self.scaling_up != 0 && !self.hand_fallback
+    }
+}
+
+/// Description of what the game was waiting on when it stopped making progress.
+pub struct Deadlock {
+    /// The tick at which we gave up.
+    pub tick: u64,
+    /// The last tick at which something happened.
+    pub last_progress_tick: u64,
+    /// The producers that have a non-empty queue.
+    pub producers: Vec<StalledProducer>,
+}
+
+impl Deadlock {
+    /// The producers that will never produce anything.
+    pub fn dead_producers(&self) -> impl Iterator<Item = &StalledProducer> {
+        self.producers.iter().filter(|p| p.is_dead())
+    }
+    /// The producers waiting for a scale up to complete.
+    pub fn blocked_scale_ups(&self) -> impl Iterator<Item = &StalledProducer> {
+        self.producers
+            .iter()
+            .filter(|p| p.is_waiting_for_scale_up())
+    }
+}
+
+impl Display for Deadlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let needs = |p: &StalledProducer| {
+            p.crafting_entity_needs
+                .iter()
+                .map(|(name, w)| {
+                    if w.is_zero() {
+                        name.clone()
+                    } else {
+                        format!("{w} {name}")
+                    }
+                })
+                .format(", ")
+                .to_string()
+        };
+        writeln!(
+            f,
+            "no progress since tick {} (now at tick {})",
+            self.last_progress_tick, self.tick
+        )?;
+        writeln!(f, "waiting on:")?;
+        let rows = self
+            .producers
+            .iter()
+            .map(|p| {
+                let state = if p.is_dead() {
+                    format!("   no {} and no way to get one", p.crafting_entity)
+                } else if p.is_waiting_for_scale_up() {
+                    format!(
+                        "   building {} {} (needs {})",
+                        p.scaling_up,
+                        p.crafting_entity,
+                        needs(p)
+                    )
+                } else {
+                    String::new()
+                };
+                [
+                    format!(" - {}", p.name),
+                    format!("  x{}", p.parallelism),
+                    format!("   {} items", p.load),
+                    state,
+                ]
+            })
+            .collect_vec();
+        writeln!(f, "{}", format_in_columns(&rows))?;
+        for p in self.dead_producers() {
+            writeln!(
+                f,
+                "{} can't make progress: it has no {} and can't craft by hand",
+                p.name, p.crafting_entity
+            )?;
+        }
+        for p in self.blocked_scale_ups() {
+            if p.blocked_on.is_empty() {
+                writeln!(
+                    f,
+                    "{} waits for a {}, but nothing is making its inputs",
+                    p.name, p.crafting_entity
+                )?;
+            } else {
+                writeln!(
+                    f,
+                    "{} waits for a {}, which is blocked on {}",
+                    p.name,
+                    p.crafting_entity,
+                    p.blocked_on.iter().format(", ")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GameState {
+    /// Describe what we're currently waiting on.
+    pub fn diagnose_deadlock(&mut self) -> Deadlock {
+        let mut hand_producers = HashSet::new();
+        let _ = self.producers.with_hand_producers(|p| {
+            hand_producers.insert((&*p as &dyn Any).type_id());
+            ControlFlow::Continue(())
+        });
+        // Which producer makes each item, to find out what a scale up is waiting on.
+        let makers: HashMap<String, String> = self
+            .graph
+            .nodes()
+            .filter_map(|node| match self.graph.kind(node) {
+                NodeKind::Produced(info) => Some((self.graph.name(node).to_owned(), info.name)),
+                _ => None,
+            })
+            .collect();
+        let mut producers: Vec<StalledProducer> = self
+            .producers
+            .iter_producers()
+            .filter(|p| p.load() != 0)
+            .sorted_by_key(|p| p.name())
+            .map(|p| {
+                let parallelism = p.available_parallelism();
+                StalledProducer {
+                    name: p.name(),
+                    load: p.load(),
+                    parallelism,
+                    scaling_up: p.projected_parallelism() - parallelism,
+                    hand_fallback: hand_producers.contains(&(&*p as &dyn Any).type_id()),
+                    crafting_entity: p.crafting_entity(),
+                    crafting_entity_needs: p.crafting_entity_needs(),
+                    blocked_on: vec![],
+                }
+            })
+            .collect();
+        let stalled: HashSet<String> = producers.iter().map(|p| p.name.clone()).collect();
+        for p in &mut producers {
+            if p.is_waiting_for_scale_up() {
+                p.blocked_on = p
+                    .crafting_entity_needs
+                    .iter()
+                    .filter_map(|(input, _)| makers.get(input))
+                    .filter(|maker| stalled.contains(*maker))
+                    .unique()
+                    .cloned()
+                    .collect();
+            }
+        }
+        Deadlock {
+            tick: self.tick.cur(),
+            last_progress_tick: self.last_progress_tick,
+            producers,
+        }
+    }
+}
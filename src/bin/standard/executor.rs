@@ -0,0 +1,156 @@
+use std::{
+    cell::{RefCell, RefMut},
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
+use crate::*;
+
+/// Shared access to the game state from async code. The state is only borrowed for the duration
+/// of each call, so that the executor can run the game while tasks are waiting.
+#[derive(Clone)]
+pub struct AsyncState(Rc<AsyncStateInner>);
+struct AsyncStateInner {
+    state: RefCell<GameState>,
+    /// Tasks spawned since the executor last looked.
+    spawned: RefCell<Vec<Pin<Box<dyn Future<Output = ()>>>>>,
+}
+
+impl AsyncState {
+    /// Access the game state. Panics if called re-entrantly.
+    pub fn with<R>(&self, f: impl FnOnce(&mut GameState) -> R) -> R {
+        f(&mut self.0.state.borrow_mut())
+    }
+    pub fn make<T: Makeable>(&self, p: Priority) -> WakeHandle<T> {
+        self.with(|state| state.make(p))
+    }
+    /// Run a task in the background. It is dropped if still pending when `block_on` returns.
+    pub fn spawn(&self, fut: impl Future<Output = ()> + 'static) {
+        self.0.spawned.borrow_mut().push(Box::pin(fut));
+    }
+    fn state_mut(&self) -> RefMut<'_, GameState> {
+        self.0.state.borrow_mut()
+    }
+}
+
+/// A waker that just records that it was woken.
+#[derive(Default)]
+struct WakeFlag(AtomicBool);
+impl WakeFlag {
+    fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+impl Wake for WakeFlag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A future along with the flag that tells us when to poll it again.
+struct Task<F: ?Sized> {
+    flag: Arc<WakeFlag>,
+    waker: Waker,
+    fut: Pin<Box<F>>,
+}
+impl<F: Future + ?Sized> Task<F> {
+    fn new(fut: Pin<Box<F>>) -> Self {
+        let flag: Arc<WakeFlag> = Default::default();
+        // Poll once to get started.
+        flag.0.store(true, Ordering::Relaxed);
+        let waker = Waker::from(flag.clone());
+        Task { flag, waker, fut }
+    }
+    /// Poll the future if it was woken.
+    fn poll_if_woken(&mut self) -> Poll<F::Output> {
+        if self.flag.take() {
+            self.fut
+                .as_mut()
+                .poll(&mut Context::from_waker(&self.waker))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl GameState {
+    /// Run an async strategy to completion, driving the game forward while it waits. Handles
+    /// returned by `AsyncState::make` can be awaited directly:
+    ///
+    /// ```ignore
+    /// let (state, steel) = state.block_on(async |s| {
+    ///     s.make::<Bundle<Steel, 5>>(p).await
+    /// });
+    /// ```
+    ///
+    /// Panics if the game gets stuck.
+    pub fn block_on<R>(self, f: impl AsyncFnOnce(AsyncState) -> R) -> (GameState, R) {
+        let state = AsyncState(Rc::new(AsyncStateInner {
+            state: RefCell::new(self),
+            spawned: Default::default(),
+        }));
+        let mut main = Task::new(Box::pin(f(state.clone())));
+        let mut tasks: Vec<Task<dyn Future<Output = ()>>> = vec![];
+        let ret = 'run: loop {
+            // Poll until everyone is waiting on the game.
+            let mut woken = true;
+            while woken {
+                if let Poll::Ready(ret) = main.poll_if_woken() {
+                    break 'run ret;
+                }
+                tasks.extend(state.0.spawned.borrow_mut().drain(..).map(Task::new));
+                tasks.retain_mut(|task| task.poll_if_woken().is_pending());
+                woken = main.flag.is_set()
+                    || tasks.iter().any(|task| task.flag.is_set())
+                    || !state.0.spawned.borrow().is_empty();
+            }
+
+            let mut game = state.state_mut();
+            game.tick_fwd();
+            if let Some(deadlock) = game.check_stalled() {
+                panic!("the game is stuck: {deadlock}")
+            }
+        };
+        // Drop the futures, which may hold onto the state.
+        drop(main);
+        drop(tasks);
+        state.0.spawned.borrow_mut().clear();
+        let inner = Rc::into_inner(state.0).expect("`AsyncState` escaped `block_on`");
+        (inner.state.into_inner(), ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::with_state;
+
+    #[test]
+    fn block_on_runs_the_game_until_the_strategy_returns() {
+        with_state(|state| {
+            let start = state.tick.cur();
+            let (state, amount) = state.block_on(async |s| {
+                let (copper, sink) = WakeHandle::<Bundle<CopperOre, 2>>::make_pipe();
+                let spawned = s.clone();
+                s.spawn(async move {
+                    let ore = spawned.make::<Bundle<CopperOre, 2>>(Priority(0)).await;
+                    spawned.with(|state| sink.give(state, ore));
+                });
+                let iron = s.make::<Bundle<IronOre, 3>>(Priority(0)).await;
+                let copper = copper.await;
+                iron.to_resource().amount() + copper.to_resource().amount()
+            });
+            assert_eq!(amount, 5);
+            // The ores are mined by hand, which takes ticks.
+            assert!(state.tick.cur() > start);
+        });
+    }
+}
@@ -1,9 +1,10 @@
 use std::{
     any::Any,
-    cmp::{Reverse, max, min},
-    collections::VecDeque,
+    cell::Cell,
+    cmp::{max, min},
     mem,
     ops::ControlFlow,
+    rc::Rc,
 };
 
 use itertools::Itertools;
@@ -117,7 +118,9 @@ impl<M: Machine> MultiMachine<M> {
     }
 
     pub fn add(&mut self, tick: &Tick, mut m: M) {
-        println!("adding a {}", type_name::<M>());
+        if verbose(Verbosity::Machines) {
+            println!("adding a {}", type_name::<M>());
+        }
         match self {
             MultiMachine::NoMachine { inputs, outputs } => {
                 for input in mem::take(inputs) {
@@ -254,6 +257,12 @@ pub trait Producer: Any + Sized {
     type Input: Any;
     type Output: Any;
     type CraftingEntity: Makeable;
+    /// The time it takes for a producing entity to make a single output.
+    const CRAFT_TIME: u64;
+    /// Whether producing entities keep working when nobody is waiting, see `busy_entities`.
+    const ALWAYS_BUSY: bool = false;
+    /// Whether the producer only exists if we start with it, e.g. a territory.
+    const FROM_START: bool = false;
 
     fn name() -> String;
 
@@ -263,8 +272,15 @@ pub trait Producer: Any + Sized {
     fn available_parallelism(&self) -> u32;
     /// Maximum number of producing entities allowed.
     fn max_parallelism(&self) -> u32;
-    /// The time it takes for a producing entity to make a single output.
-    fn craft_time(&self) -> u64;
+    /// See `CRAFT_TIME`.
+    fn craft_time(&self) -> u64 {
+        Self::CRAFT_TIME
+    }
+    /// Number of producing entities that are crafting on their own, given the number of waiters.
+    /// By default entities only work when they've been fed inputs, one input per waiter.
+    fn busy_entities(&self, load: usize) -> u32 {
+        min(self.available_parallelism(), load as u32)
+    }
 
     /// Detailed load reporting, if available.
     fn report_load(&mut self, _tick: &Tick) -> Option<String> {
@@ -277,12 +293,24 @@ pub trait Producer: Any + Sized {
     fn poll(&mut self, tick: &Tick) -> Option<Self::Output>;
 
     /// Turn a receiver of outputs into a receiver of inputs.
-    fn feed(p: Priority, sink: Sink<Self::Output>) -> StateSink<Self::Input> {
+    fn feed(
+        task: Option<TaskId>,
+        p: SharedPriority,
+        sink: Sink<Self::Output>,
+    ) -> StateSink<Self::Input> {
         StateSink::from_fn(move |state, inputs| {
-            state
-                .producers
-                .producer::<Self>()
-                .feed(&state.tick, &mut state.queue, p, inputs, sink);
+            if let Some((node, amount)) = state.graph.producer_node(TypeId::of::<Self>()) {
+                state.graph.record_consumption(node, amount);
+            }
+            let aging = state.producers.aging;
+            let this = state.producers.producer::<Self>();
+            let waiter = (task, p.clone());
+            this.feed(&state.tick, &mut state.queue, aging, waiter, inputs, sink);
+            if this.producer.available_parallelism() == 0 {
+                // We can't be served until a producing entity gets built, so that must happen at
+                // our priority at least. Scale-ups run one level above their waiters.
+                state.bump_scale_ups::<Self>(Priority(p.get().0 + 1));
+            }
         })
     }
 
@@ -292,14 +320,19 @@ pub trait Producer: Any + Sized {
     /// Trigger a scaling up. This ensures we don't scale up many times in parallel.
     fn trigger_scale_up(p: Priority) -> Box<dyn FnOnce(&mut GameState) -> bool> {
         Box::new(move |state| {
-            eprintln!("scaling up {}", type_name::<Self>());
+            if verbose(Verbosity::Machines) {
+                eprintln!("scaling up {}", type_name::<Self>());
+            }
             let this = state.producer::<Self>();
             this.scaling_up += 1;
             let add_entity = StateSink::from_fn(|state, entity| {
                 state.producer::<Self>().scaling_up -= 1;
                 Self::scale_up(state, entity);
             });
-            state.make_to(p, add_entity);
+            let id = state.make_to(p, add_entity);
+            if !state.tasks.is_finished(id) {
+                state.producer::<Self>().scale_up_tasks.push(id);
+            }
             true
         })
     }
@@ -309,6 +342,7 @@ impl<R: HandRecipe + ConstRecipe + Any> Producer for HandCrafter<R> {
     type Input = <R as ConstRecipe>::BundledInputs;
     type Output = <R as ConstRecipe>::BundledOutputs;
     type CraftingEntity = ();
+    const CRAFT_TIME: u64 = <R as Recipe>::TIME;
 
     fn name() -> String {
         type_name::<R>()
@@ -322,8 +356,9 @@ impl<R: HandRecipe + ConstRecipe + Any> Producer for HandCrafter<R> {
     fn max_parallelism(&self) -> u32 {
         1
     }
-    fn craft_time(&self) -> u64 {
-        <R as Recipe>::TIME
+    fn busy_entities(&self, _load: usize) -> u32 {
+        // Crafting by hand happens separately.
+        0
     }
 
     fn add_inputs(&mut self, _tick: &Tick, inputs: Self::Input) {
@@ -338,6 +373,9 @@ impl<Ore: OreType + Any> Producer for Territory<Ore> {
     type Input = ();
     type Output = (Bundle<Ore, 1>,);
     type CraftingEntity = Miner;
+    const CRAFT_TIME: u64 = Ore::MINING_TIME;
+    const ALWAYS_BUSY: bool = true;
+    const FROM_START: bool = true;
 
     fn name() -> String {
         type_name::<Ore>()
@@ -351,8 +389,9 @@ impl<Ore: OreType + Any> Producer for Territory<Ore> {
     fn max_parallelism(&self) -> u32 {
         self.max_miners()
     }
-    fn craft_time(&self) -> u64 {
-        Ore::MINING_TIME
+    fn busy_entities(&self, _load: usize) -> u32 {
+        // Miners keep mining whether or not someone is waiting.
+        self.num_miners()
     }
 
     fn add_inputs(&mut self, _tick: &Tick, _inputs: Self::Input) {}
@@ -377,6 +416,7 @@ where
     type Input = <M::Recipe as ConstRecipe>::BundledInputs;
     type Output = <M::Recipe as ConstRecipe>::BundledOutputs;
     type CraftingEntity = M;
+    const CRAFT_TIME: u64 = <M::Recipe as Recipe>::TIME;
 
     fn name() -> String {
         type_name::<M::Recipe>()
@@ -390,9 +430,6 @@ where
     fn max_parallelism(&self) -> u32 {
         u32::MAX
     }
-    fn craft_time(&self) -> u64 {
-        <M::Recipe as Recipe>::TIME
-    }
     fn report_load(&mut self, tick: &Tick) -> Option<String> {
         match self {
             MultiMachine::Present(machines) => Some(
@@ -502,6 +539,7 @@ where
     type Input = ();
     type Output = Available<O>;
     type CraftingEntity = <O as OnceMakeable>::Input;
+    const CRAFT_TIME: u64 = 0;
 
     fn name() -> String {
         type_name::<Self>()
@@ -515,7 +553,7 @@ where
     fn max_parallelism(&self) -> u32 {
         1
     }
-    fn craft_time(&self) -> u64 {
+    fn busy_entities(&self, _load: usize) -> u32 {
         0
     }
 
@@ -531,16 +569,132 @@ where
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Waiters with a larger priority are served first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Priority(pub u16);
 
+/// A priority that can be raised after the fact. Shared between a request and the waiters it
+/// enqueued.
+#[derive(Clone)]
+pub struct SharedPriority(Rc<Cell<Priority>>);
+
+impl SharedPriority {
+    pub fn new(p: Priority) -> Self {
+        Self(Rc::new(Cell::new(p)))
+    }
+    pub fn get(&self) -> Priority {
+        self.0.get()
+    }
+    pub fn set(&self, p: Priority) {
+        self.0.set(p);
+    }
+    /// Raise the priority to at least `p`. Returns whether it changed.
+    pub fn raise(&self, p: Priority) -> bool {
+        let changed = p > self.get();
+        if changed {
+            self.0.set(p);
+        }
+        changed
+    }
+}
+
+/// How the priority of waiters grows as they wait, so that low-priority waiters don't get starved
+/// by a steady stream of higher-priority ones.
+#[derive(Clone, Copy)]
+pub struct Aging {
+    /// A waiter gains one priority level every that many ticks. `None` disables aging.
+    pub ticks_per_level: Option<u64>,
+    /// Waiters that have waited longer than this are reported as starving.
+    pub starvation_threshold: u64,
+}
+
+impl Default for Aging {
+    fn default() -> Self {
+        Self {
+            ticks_per_level: None,
+            starvation_threshold: 2000,
+        }
+    }
+}
+
+impl Aging {
+    /// Key to sort waiters by, larger first. A waiter's effective priority at tick `now` is
+    /// `p + (now - since) / ticks_per_level`; comparing effective priorities is the same as
+    /// comparing `p * ticks_per_level - since`, which doesn't depend on `now`. Hence the queue
+    /// order doesn't change as time passes. Saturates rather than overflowing.
+    pub fn sort_key(&self, p: Priority, since: u64) -> i64 {
+        match self.ticks_per_level {
+            Some(k) => i64::from(p.0)
+                .saturating_mul(i64::try_from(k).unwrap_or(i64::MAX))
+                .saturating_sub(i64::try_from(since).unwrap_or(i64::MAX)),
+            None => i64::from(p.0),
+        }
+    }
+}
+
+/// Whether a producer that already has `parallelism` producing entities (built or underway, of
+/// which `scaling_up` underway) should build one more to serve `load` waiters.
+pub fn load_warrants_scale_up(load: u32, parallelism: u32, scaling_up: u32) -> bool {
+    load > (parallelism + scaling_up * 5) * 4
+}
+
+/// An entry in a `ProducerWithQueue` queue.
+pub struct Waiter<T> {
+    pub sink: Sink<T>,
+    /// The request this waiter is part of, if any.
+    pub task: Option<TaskId>,
+    pub priority: SharedPriority,
+    /// Tick at which the waiter was enqueued.
+    pub since: u64,
+}
+
+impl<T> Waiter<T> {
+    /// Whether the request this waiter is part of was cancelled.
+    pub fn is_cancelled(&self, tasks: &Tasks) -> bool {
+        self.task.is_some_and(|id| tasks.is_cancelled(id))
+    }
+}
+
 /// A producer along with a queue of items waiting on it.
 pub struct ProducerWithQueue<P: Producer> {
     pub producer: P,
-    /// Keep sorted by priority.
-    pub queue: VecDeque<(Sink<P::Output>, Priority)>,
+    /// Ordered by priority, see `Aging::sort_key`.
+    pub queue: WaiterQueue<Waiter<P::Output>>,
     /// Number of producing entities we're in the process of building.
     pub scaling_up: u32,
+    /// The requests for the producing entities we're building in `trigger_scale_up`. May contain
+    /// finished requests.
+    scale_up_tasks: Vec<TaskId>,
+    /// One entry per busy producing entity: a lower bound on the tick at which it will next yield
+    /// an output. Used to skip ticks during which nothing can happen.
+    next_outputs: Vec<u64>,
+    /// Last tick at which polling gave nothing. Any output we see later was made after that tick.
+    quiet_tick: u64,
+    /// `available_parallelism` when we last polled. If it changed, outputs may have appeared
+    /// outside of the usual schedule (e.g. handcrafted outputs moved into a new machine).
+    polled_parallelism: u32,
+}
+
+/// Update the lower bounds on the next outputs of the busy entities after one of them yielded an
+/// output at tick `now`. We don't know which one: any entity whose bound has passed may have.
+fn record_output(next_outputs: &mut [u64], now: u64, quiet_tick: u64, craft_time: u64) {
+    let mut candidates = next_outputs.iter().positions(|&t| t <= now).collect_vec();
+    if candidates.is_empty() {
+        // Our bounds were off; any entity may have finished.
+        candidates = (0..next_outputs.len()).collect();
+    }
+    let Some(earliest) = candidates.iter().map(|&i| next_outputs[i]).min() else {
+        return;
+    };
+    // Whichever entity finished, it did so at the earliest right after the last time we saw
+    // nothing, and if it has more work it started the next craft right away. Entries aren't tied
+    // to entities, only the set of bounds matters: replacing the latest candidate bound keeps the
+    // set valid whichever candidate it was.
+    let latest = candidates
+        .into_iter()
+        .max_by_key(|&i| next_outputs[i])
+        .unwrap();
+    next_outputs[latest] = max(earliest, quiet_tick + 1) + craft_time;
 }
 
 impl<P: Producer> ProducerWithQueue<P> {
@@ -549,47 +703,149 @@ impl<P: Producer> ProducerWithQueue<P> {
             producer,
             queue: Default::default(),
             scaling_up: Default::default(),
+            scale_up_tasks: Default::default(),
+            next_outputs: Default::default(),
+            quiet_tick: Default::default(),
+            polled_parallelism: Default::default(),
+        }
+    }
+
+    /// Poll the producer, keeping track of when its entities will next yield outputs.
+    fn poll(&mut self, tick: &Tick) -> Option<P::Output> {
+        let output = self.producer.poll(tick);
+        if output.is_some() {
+            record_output(
+                &mut self.next_outputs,
+                tick.cur(),
+                self.quiet_tick,
+                self.producer.craft_time(),
+            );
+        } else {
+            self.quiet_tick = tick.cur();
+            for t in &mut self.next_outputs {
+                *t = max(*t, tick.cur() + 1);
+            }
+        }
+        output
+    }
+
+    /// Keep one entry in `next_outputs` per busy producing entity.
+    fn sync_busy_entities(&mut self, now: u64) {
+        let busy = self.producer.busy_entities(self.queue.len()) as usize;
+        // An entity that just got work can't finish before a full craft. We allow for one tick of
+        // slack to be safe.
+        let earliest = now + self.producer.craft_time().saturating_sub(1);
+        while self.next_outputs.len() < busy {
+            self.next_outputs.push(earliest);
+        }
+        // The entities that run out of work are the ones furthest from their next output.
+        while self.next_outputs.len() > busy {
+            let i = self.next_outputs.iter().position_max().unwrap();
+            self.next_outputs.swap_remove(i);
+        }
+    }
+
+    /// Number of waiters that have been waiting since before `tick`.
+    pub fn waiting_since_before(&self, tick: u64) -> usize {
+        self.queue.iter().filter(|w| w.since < tick).count()
+    }
+
+    /// Whether polling the producer may yield something at this tick.
+    pub fn may_have_output(&self, now: u64) -> bool {
+        !self.queue.is_empty()
+            && (self.next_outputs.is_empty()
+                || self.polled_parallelism != self.producer.available_parallelism()
+                || self.next_outputs.iter().any(|&t| t <= now))
+    }
+
+    /// The earliest tick at which this producer may hand out an output. `None` if that can only
+    /// happen because of some other event.
+    pub fn next_output_tick(&mut self, now: u64) -> Option<u64> {
+        if self.queue.is_empty() {
+            return None;
         }
+        if self.scale_up_if_needed().is_some()
+            || self.polled_parallelism != self.producer.available_parallelism()
+        {
+            return Some(now + 1);
+        }
+        self.sync_busy_entities(now);
+        self.next_outputs.iter().min().map(|&t| max(t, now + 1))
     }
 
     fn enqueue(
         &mut self,
         tick: &Tick,
         waiters: &mut CallBackQueue,
+        aging: Aging,
         sink: Sink<P::Output>,
-        p: Priority,
+        (task, p): (Option<TaskId>, SharedPriority),
     ) {
         if self.queue.is_empty()
-            && let Some(output) = self.producer.poll(tick)
+            && let Some(output) = self.poll(tick)
         {
             sink.give(waiters, output);
         } else {
-            self.queue.push_back((sink, p));
-            self.queue
-                .make_contiguous()
-                .sort_by_key(|(_, p)| Reverse(*p));
+            let since = tick.cur();
+            let key = aging.sort_key(p.get(), since);
+            let waiter = Waiter {
+                sink,
+                task,
+                priority: p,
+                since,
+            };
+            self.queue.push(key, waiter);
         }
+        self.sync_busy_entities(tick.cur());
     }
     /// Feed the producer some inputs and somewhere to put the generated output.
     pub fn feed(
         &mut self,
         tick: &Tick,
         waiters: &mut CallBackQueue,
-        p: Priority,
+        aging: Aging,
+        waiter: (Option<TaskId>, SharedPriority),
         inputs: P::Input,
         sink: Sink<P::Output>,
     ) {
         self.producer.add_inputs(tick, inputs);
-        self.enqueue(tick, waiters, sink, p);
+        self.enqueue(tick, waiters, aging, sink, waiter);
     }
 
-    pub fn update(&mut self, tick: &Tick, waiters: &mut CallBackQueue) {
-        while !self.queue.is_empty()
-            && let Some(output) = self.producer.poll(tick)
-        {
-            let (sink, _) = self.queue.pop_front().unwrap();
-            sink.give(waiters, output);
+    /// Drop the waiters that belong to cancelled requests. Returns how many were removed.
+    pub fn remove_cancelled(&mut self, tasks: &Tasks) -> usize {
+        let len = self.queue.len();
+        self.queue.retain(|w| !w.is_cancelled(tasks));
+        len - self.queue.len()
+    }
+
+    /// Sort the queue again, e.g. after some priorities were raised.
+    pub fn resort(&mut self, aging: Aging) {
+        self.queue
+            .rekey(|w| aging.sort_key(w.priority.get(), w.since));
+    }
+
+    /// Hand out available outputs to waiters. Returns the number of outputs handed out.
+    pub fn update(&mut self, tick: &Tick, waiters: &mut CallBackQueue, tasks: &Tasks) -> usize {
+        let mut delivered = 0;
+        loop {
+            // Callbacks may have enqueued waiters for requests that were already cancelled.
+            while self.queue.peek().is_some_and(|w| w.is_cancelled(tasks)) {
+                self.queue.pop();
+            }
+            if self.queue.is_empty() {
+                break;
+            }
+            let Some(output) = self.poll(tick) else {
+                break;
+            };
+            let waiter = self.queue.pop().unwrap();
+            waiter.sink.give(waiters, output);
+            delivered += 1;
         }
+        self.polled_parallelism = self.producer.available_parallelism();
+        self.sync_busy_entities(tick.cur());
+        delivered
     }
 
     /// Checks if scaling up may be needed. If so, return a function to be called on the game state
@@ -627,19 +883,19 @@ impl<P: Producer> ProducerWithQueue<P> {
                     // Simple heuristic: if crafting an extra machine would take less time than the time won by
                     // scaling up, scale up.
                     if time_left_with_upscale + machine_crafting_time < real_time_left {
-                        let p = this.queue.front().unwrap().1;
+                        let p = this.queue.peek().unwrap().priority.get();
                         let p = Priority(p.0 + 1);
                         P::trigger_scale_up(p)(state)
                     } else {
                         false
                     }
                 }));
-            } else if load <= (parallelism + self.scaling_up * 5) * 4 {
+            } else if !load_warrants_scale_up(load, parallelism, self.scaling_up) {
                 return None;
             }
         }
 
-        let p = self.queue.front().unwrap().1;
+        let p = self.queue.peek().unwrap().priority.get();
         let p = Priority(p.0 + 1);
         Some(P::trigger_scale_up(p))
     }
@@ -651,7 +907,9 @@ impl<P: Producer> ProducerWithQueue<P> {
         P: HandProducer,
     {
         if !self.producer.can_craft_automatically() && !self.queue.is_empty() {
-            println!("crafting by hand with {}", P::name());
+            if verbose(Verbosity::All) {
+                println!("crafting by hand with {}", P::name());
+            }
             self.producer.craft_by_hand(tick)
         } else {
             ControlFlow::Continue(())
@@ -665,17 +923,36 @@ impl GameState {
         <P>::trigger_scale_up(p)(self);
     }
 
+    /// Change how waiter priorities grow with time. The queues are sorted again so that waiters
+    /// enqueued before and after the change are compared alike.
+    pub fn set_aging(&mut self, aging: Aging) {
+        self.producers.aging = aging;
+        self.resort_queues();
+    }
+
+    /// Raise the pending scale-ups of this producer, along with their sub-requests, to at least
+    /// priority `p`.
+    pub fn bump_scale_ups<P: Producer>(&mut self, p: Priority) {
+        let tasks = &mut self.tasks;
+        let this = self.producers.producer::<P>();
+        this.scale_up_tasks.retain(|&id| !tasks.is_finished(id));
+        let mut changed = false;
+        for &id in &this.scale_up_tasks {
+            changed |= tasks.bump(id, p);
+        }
+        if changed {
+            self.resort_queues();
+        }
+    }
+
     pub fn add_miner<Ore: OreType + Any>(&mut self, p: Priority) {
         self.scale_up::<Territory<Ore>>(p)
     }
 
     pub fn add_machine<M: Machine + Makeable>(&mut self, p: Priority) -> WakeHandle<()> {
-        self.handle_via_state_sink(|state, sink| {
-            // TODO: If we use `trigger_scale_up` then we lose some parallelism :(
-            let add_machine = sink.map(|state, machine| {
-                <MultiMachine<M>>::scale_up(state, machine);
-            });
-            state.make_to(p, add_machine);
+        // TODO: If we use `trigger_scale_up` then we lose some parallelism :(
+        self.make::<M>(p).map(self, |state, machine| {
+            <MultiMachine<M>>::scale_up(state, machine);
         })
     }
     pub fn add_assembler<R>(&mut self, p: Priority) -> WakeHandle<()>
@@ -700,3 +977,62 @@ impl GameState {
         self.add_machine::<Lab<T>>(p)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_key_without_aging_is_the_priority() {
+        let aging = Aging::default();
+        assert_eq!(aging.sort_key(Priority(3), 0), 3);
+        assert_eq!(aging.sort_key(Priority(3), 1000), 3);
+    }
+
+    #[test]
+    fn sort_key_lets_old_waiters_catch_up() {
+        let aging = Aging {
+            ticks_per_level: Some(100),
+            ..Default::default()
+        };
+        // One level above, but enqueued 100 ticks later: same effective priority.
+        assert_eq!(
+            aging.sort_key(Priority(1), 100),
+            aging.sort_key(Priority(0), 0)
+        );
+        // Enqueued 150 ticks later, it has to wait for the older one.
+        assert!(aging.sort_key(Priority(1), 150) < aging.sort_key(Priority(0), 0));
+        assert!(aging.sort_key(Priority(1), 50) > aging.sort_key(Priority(0), 0));
+    }
+
+    #[test]
+    fn sort_key_saturates() {
+        let aging = Aging {
+            ticks_per_level: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert_eq!(aging.sort_key(Priority(2), 10), i64::MAX - 10);
+        assert_eq!(aging.sort_key(Priority(0), u64::MAX), -i64::MAX);
+        assert!(aging.sort_key(Priority(1), 0) > aging.sort_key(Priority(0), 0));
+    }
+
+    #[test]
+    fn record_output_keeps_the_bounds_of_pending_entities() {
+        // The entities bounded at 3 and 5 may both have finished by tick 5. If it was the second
+        // one, the first may still yield at tick 6, so its bound must stay.
+        let mut next_outputs = vec![3, 5, 20];
+        record_output(&mut next_outputs, 5, 2, 10);
+        assert_eq!(next_outputs, vec![3, 13, 20]);
+    }
+
+    #[test]
+    fn record_output_with_stale_bounds() {
+        let mut next_outputs = vec![8, 9];
+        record_output(&mut next_outputs, 5, 4, 10);
+        assert_eq!(next_outputs, vec![8, 18]);
+
+        let mut next_outputs = vec![];
+        record_output(&mut next_outputs, 5, 4, 10);
+        assert!(next_outputs.is_empty());
+    }
+}
@@ -9,6 +9,7 @@
 )]
 #![allow(incomplete_features)]
 use indexmap::IndexMap;
+use itertools::Itertools;
 use std::{
     any::{Any, TypeId},
     collections::HashMap,
@@ -31,24 +32,59 @@ pub use rustorio::{
 pub use rustorio_engine::research::TechRecipe;
 
 mod analysis;
+mod build_order;
+mod cli;
 mod crafting;
+mod critical_path;
+mod deadlock;
+mod executor;
 mod machine;
+mod metrics;
+mod rates;
+mod ratio;
 mod recipes;
+mod reporter;
 mod resources;
 mod runtime;
 mod scheduler;
+mod search;
+mod sim;
+#[cfg(test)]
+mod test_utils;
+mod trace;
 mod utils;
+mod validate;
+mod waiter_queue;
 pub use analysis::*;
+pub use build_order::*;
+pub use cli::*;
 pub use crafting::*;
+pub use critical_path::*;
+pub use deadlock::*;
+pub use executor::*;
 pub use machine::*;
+pub use metrics::*;
+pub use rates::*;
+pub use ratio::*;
 pub use recipes::*;
+pub use reporter::*;
 pub use resources::*;
 pub use runtime::*;
 pub use scheduler::*;
+pub use search::*;
+pub use sim::*;
+pub use trace::*;
 pub use utils::*;
+pub use validate::*;
+pub use waiter_queue::*;
 
 type GameMode = Standard;
 
+/// Where to write the task trace at the end of the game.
+const TRACE_PATH: &str = "trace.json";
+/// Where to write the metrics time series at the end of the game.
+const METRICS_PATH: &str = "metrics.csv";
+
 type StartingResources = <GameMode as rustorio::GameMode>::StartingResources;
 
 pub struct Victory(<GameMode as rustorio::GameMode>::VictoryResources);
@@ -65,9 +101,54 @@ fn main() {
         tick: Tick,
         starting_resources: StartingResources,
     ) -> (Tick, <GameMode as rustorio::GameMode>::VictoryResources) {
-        let (tick, v) = GameState::new(tick, starting_resources).play();
+        let cli = Cli::get();
+        let mut state = GameState::new(tick, starting_resources);
+        cli.configure(&mut state);
+        let build_order = || match load_build_order(CONFIG_PATH) {
+            Ok(build_order) => build_order,
+            Err(e) => {
+                eprintln!("could not read the build order from `{CONFIG_PATH}`: {e}");
+                std::process::exit(1);
+            }
+        };
+        match cli.strategy {
+            Strategy::Simulate => {
+                match state.simulate(&build_order()) {
+                    Ok(report) => print!("{report}"),
+                    Err(e) => {
+                        eprintln!("could not simulate the build order: {e}");
+                        std::process::exit(1);
+                    }
+                }
+                std::process::exit(0);
+            }
+            Strategy::Search => {
+                let result = match state.search_build_order(SearchParams::default()) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        eprintln!("could not search for a build order: {e}");
+                        std::process::exit(1);
+                    }
+                };
+                println!("evaluated {} build orders", result.evaluated);
+                print!("{}", result.report);
+                print!("\n{}\n{}", result.rust, result.toml);
+                std::process::exit(0);
+            }
+            Strategy::Bom => {
+                print!("{}", ResourceGraph::bill_of_materials::<Victory>());
+                std::process::exit(0);
+            }
+            Strategy::Rates => {
+                print!("{}", state.machine_ratios::<Point>(cli.rate));
+                std::process::exit(0);
+            }
+            Strategy::Play => {}
+        }
+        let (tick, v) = state.play(&build_order());
         (tick, v.0)
     }
+    Cli::init();
     rustorio::play::<GameMode>(user_main);
 }
 
@@ -94,14 +175,38 @@ impl Resources {
 #[derive(Default)]
 pub struct Producers {
     producers: IndexMap<TypeId, Box<dyn ErasedProducer>>,
+    /// How waiter priorities grow with time. Applies to waiters enqueued afterwards.
+    pub aging: Aging,
+}
+
+/// Snapshot of the state of a producer.
+#[derive(Clone)]
+pub struct ProducerLoad {
+    pub name: String,
+    /// Number of waiters in the queue.
+    pub load: usize,
+    /// Number of waiters that have waited longer than `Aging::starvation_threshold`.
+    pub starving: usize,
+    pub parallelism: u32,
+    /// Number of producing entities we're in the process of building.
+    pub scaling_up: u32,
+    /// See `ErasedProducer::time_left`.
+    pub time_left: Option<u64>,
+    /// Detailed load reporting, if available.
+    pub detail: Option<String>,
 }
 
 pub trait ErasedProducer: Any {
+    /// The `TypeId` of the underlying producer, as used to key `Producers`.
+    fn producer_id(&self) -> TypeId;
     fn name(&self) -> String;
     fn available_parallelism(&self) -> u32;
     fn projected_parallelism(&self) -> u32;
+    fn max_parallelism(&self) -> u32;
     fn craft_time(&self) -> u64;
     fn load(&self) -> usize;
+    /// Number of waiters that have been waiting since before `tick`.
+    fn waiting_since_before(&self, tick: u64) -> usize;
     /// Estimate the time left to produce the current load. `None` if waiting for an external
     /// event.
     fn time_left(&self) -> Option<u64> {
@@ -117,10 +222,27 @@ pub trait ErasedProducer: Any {
         }
     }
     fn report_load(&mut self, tick: &Tick) -> Option<String>;
-    fn update(&mut self, tick: &Tick, waiters: &mut CallBackQueue);
+    /// Name of the entity we build to scale up this producer.
+    fn crafting_entity(&self) -> String;
+    /// What building one more crafting entity takes, as `(resource, quantity)` pairs.
+    fn crafting_entity_needs(&self) -> Vec<(String, Ratio)>;
+    /// Hand out available outputs to waiters. Returns the number of outputs handed out.
+    fn update(&mut self, tick: &Tick, waiters: &mut CallBackQueue, tasks: &Tasks) -> usize;
+    /// Whether `update` may hand out anything at this tick.
+    fn may_have_output(&self, now: u64) -> bool;
+    /// The earliest tick at which this producer may hand out an output. `None` if that can only
+    /// happen because of some other event.
+    fn next_output_tick(&mut self, now: u64) -> Option<u64>;
     fn scale_up_if_needed(&mut self) -> Option<Box<dyn FnOnce(&mut GameState) -> bool>>;
+    /// Sort the queue again, e.g. after some priorities were raised.
+    fn resort(&mut self, aging: Aging);
+    /// Drop the waiters that belong to cancelled requests. Returns how many were removed.
+    fn remove_cancelled(&mut self, tasks: &Tasks) -> usize;
 }
 impl<P: Producer> ErasedProducer for ProducerWithQueue<P> {
+    fn producer_id(&self) -> TypeId {
+        TypeId::of::<P>()
+    }
     fn name(&self) -> String {
         P::name()
     }
@@ -130,27 +252,60 @@ impl<P: Producer> ErasedProducer for ProducerWithQueue<P> {
     fn projected_parallelism(&self) -> u32 {
         self.available_parallelism() + self.scaling_up
     }
+    fn max_parallelism(&self) -> u32 {
+        self.producer.max_parallelism()
+    }
     fn craft_time(&self) -> u64 {
         self.producer.craft_time()
     }
     fn load(&self) -> usize {
         self.queue.len()
     }
+    fn waiting_since_before(&self, tick: u64) -> usize {
+        self.waiting_since_before(tick)
+    }
     fn report_load(&mut self, tick: &Tick) -> Option<String> {
         self.producer.report_load(tick)
     }
-    fn update(&mut self, tick: &Tick, waiters: &mut CallBackQueue) {
-        self.update(tick, waiters);
+    fn crafting_entity(&self) -> String {
+        type_name::<P::CraftingEntity>()
+    }
+    fn crafting_entity_needs(&self) -> Vec<(String, Ratio)> {
+        ResourceGraph::requirements::<P::CraftingEntity>()
+    }
+    fn update(&mut self, tick: &Tick, waiters: &mut CallBackQueue, tasks: &Tasks) -> usize {
+        self.update(tick, waiters, tasks)
+    }
+    fn may_have_output(&self, now: u64) -> bool {
+        self.may_have_output(now)
+    }
+    fn next_output_tick(&mut self, now: u64) -> Option<u64> {
+        self.next_output_tick(now)
     }
     fn scale_up_if_needed(&mut self) -> Option<Box<dyn FnOnce(&mut GameState) -> bool>> {
         self.scale_up_if_needed()
     }
+    fn resort(&mut self, aging: Aging) {
+        self.resort(aging)
+    }
+    fn remove_cancelled(&mut self, tasks: &Tasks) -> usize {
+        self.remove_cancelled(tasks)
+    }
 }
 
 pub trait ErasedHandProducer: Any {
+    /// The `TypeId` of the underlying producer, as used to key `Producers`.
+    fn producer_id(&self) -> TypeId;
+    fn can_craft_automatically(&self) -> bool;
     fn craft_by_hand_if_needed(&mut self, tick: &mut Tick) -> ControlFlow<AdvancedTick>;
 }
 impl<P: HandProducer> ErasedHandProducer for ProducerWithQueue<P> {
+    fn producer_id(&self) -> TypeId {
+        TypeId::of::<P>()
+    }
+    fn can_craft_automatically(&self) -> bool {
+        self.producer.can_craft_automatically()
+    }
     fn craft_by_hand_if_needed(&mut self, tick: &mut Tick) -> ControlFlow<AdvancedTick> {
         self.craft_by_hand_if_needed(tick)
     }
@@ -180,9 +335,51 @@ impl Producers {
         let storage: &mut (dyn Any + 'static) = storage;
         storage.downcast_mut().unwrap()
     }
+    /// Gets the producer with this `TypeId` if there is one.
+    pub fn get(&self, id: TypeId) -> Option<&dyn ErasedProducer> {
+        self.producers.get(&id).map(|p| p.as_ref())
+    }
     pub fn iter_producers(&mut self) -> impl Iterator<Item = &mut dyn ErasedProducer> {
         self.producers.values_mut().map(|s| s.as_mut())
     }
+    /// Sort all the queues again, e.g. after some priorities were raised.
+    pub fn resort(&mut self) {
+        let aging = self.aging;
+        for p in self.iter_producers() {
+            p.resort(aging);
+        }
+    }
+    /// Drop the waiters that belong to cancelled requests.
+    pub fn remove_cancelled(&mut self, tasks: &Tasks) {
+        for p in self.iter_producers() {
+            p.remove_cancelled(tasks);
+        }
+    }
+    /// The earliest tick at which any producer may hand out an output.
+    pub fn next_output_tick(&mut self, now: u64) -> Option<u64> {
+        self.iter_producers()
+            .filter_map(|p| p.next_output_tick(now))
+            .min()
+    }
+    /// Snapshot the state of every producer, sorted by name.
+    pub fn loads(&mut self, tick: &Tick) -> Vec<ProducerLoad> {
+        let starving_since = tick.cur().saturating_sub(self.aging.starvation_threshold);
+        self.iter_producers()
+            .sorted_by_key(|p| p.name())
+            .map(|p| {
+                let parallelism = p.available_parallelism();
+                ProducerLoad {
+                    name: p.name(),
+                    load: p.load(),
+                    starving: p.waiting_since_before(starving_since),
+                    parallelism,
+                    scaling_up: p.projected_parallelism() - parallelism,
+                    time_left: p.time_left(),
+                    detail: p.report_load(tick),
+                }
+            })
+            .collect()
+    }
 
     /// Gets or creates a producer of this type.
     pub fn producer<P: Producer>(&mut self) -> &mut ProducerWithQueue<P> {
@@ -220,47 +417,36 @@ impl Producers {
 }
 
 impl GameState {
-    fn play(mut self) -> (Tick, Victory) {
-        let p = Priority(4);
-        // Start with this one otherwise we're stuck.
-        self.scale_up::<MultiMachine<Furnace<IronSmelting>>>(p);
-        // self.add_furnace::<IronSmelting>(p);
-        // self.add_furnace::<CopperSmelting>(p);
-
-        // self.add_miner::<IronOre>(p);
-        // self.add_miner::<CopperOre>(p);
-
-        self.add_furnace::<IronSmelting>(p);
-        // self.add_furnace::<CopperSmelting>(p);
-
-        let h = self.add_assembler::<CopperWireRecipe>(p);
-        self.complete(h);
-
-        // self.add_furnace::<IronSmelting>(p);
-        // self.add_furnace::<IronSmelting>(p);
-
-        // self.scale_up::<OnceMaker<SteelSmelting>>(p);
-        // self.add_furnace::<IronSmelting>(p);
-        // self.add_furnace::<IronSmelting>(p);
-        self.add_miner::<CopperOre>(p);
-        self.add_miner::<CopperOre>(p);
-        self.add_miner::<CopperOre>(p);
-        // self.add_miner::<IronOre>(p);
-        // self.add_furnace::<CopperSmelting>(p);
-
-        // self.add_assembler::<ElectronicCircuitRecipe>(p);
-
-        // self.scale_up::<OnceMaker<PointRecipe>>(p);
-
-        self.add_furnace::<SteelSmelting>(p);
-        // self.add_furnace::<SteelSmelting>(p);
+    fn play(mut self, build_order: &[Action]) -> (Tick, Victory) {
+        if let Err(errors) = self.validate_graph::<Victory>() {
+            eprintln!("cannot win the game:");
+            for e in errors {
+                eprintln!("  {e}");
+            }
+            std::process::exit(1);
+        }
+        self.run_build_order(build_order);
+        if verbose(Verbosity::Machines) {
+            print!("{}", self.critical_path::<Victory>());
+        }
 
         let victory = self.make(Priority(0));
 
         let victory: Victory = self.complete(victory);
 
         self.graph.set_display_root::<Victory>();
-        println!("{}", self.graph);
+        match Cli::get().graph_format {
+            GraphFormat::Table => println!("{}", self.graph),
+            GraphFormat::Dot => print!("{}", self.graph.to_dot()),
+            GraphFormat::None => {}
+        }
+
+        if let Err(e) = self.write_trace(TRACE_PATH) {
+            eprintln!("could not write `{TRACE_PATH}`: {e}");
+        }
+        if let Err(e) = self.write_metrics(METRICS_PATH) {
+            eprintln!("could not write `{METRICS_PATH}`: {e}");
+        }
 
         (self.tick.into_inner(), victory)
     }
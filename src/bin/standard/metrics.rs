@@ -0,0 +1,124 @@
+use std::{fs, io, path::Path};
+
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::*;
+
+/// Default number of ticks between two metrics samples.
+pub const DEFAULT_METRICS_PERIOD: u64 = 10;
+
+/// The state of a producer at some tick.
+#[derive(Clone, Copy)]
+pub struct ProducerMetrics {
+    pub load: usize,
+    pub available_parallelism: u32,
+    pub projected_parallelism: u32,
+    pub time_left: Option<u64>,
+}
+
+/// Time series of the state of every producer.
+pub struct Metrics {
+    /// Number of ticks between two samples.
+    period: u64,
+    /// The next tick to sample.
+    next_sample: u64,
+    /// The state of the producers the last time we looked. Producers don't change during the
+    /// ticks `tick_fwd` skips, so this is also their state during those ticks.
+    last: IndexMap<String, ProducerMetrics>,
+    samples: Vec<(u64, IndexMap<String, ProducerMetrics>)>,
+}
+
+impl Metrics {
+    pub fn new(period: u64) -> Self {
+        assert!(period > 0);
+        Self {
+            period,
+            next_sample: 0,
+            last: Default::default(),
+            samples: vec![],
+        }
+    }
+
+    /// Record the samples due up to `now`. Call once the tick is done.
+    pub fn record(&mut self, now: u64, producers: &mut Producers) {
+        if now < self.next_sample {
+            return;
+        }
+        // The ticks we skipped over.
+        while self.next_sample < now {
+            self.samples.push((self.next_sample, self.last.clone()));
+            self.next_sample += self.period;
+        }
+        self.last = producers
+            .iter_producers()
+            .map(|p| {
+                let metrics = ProducerMetrics {
+                    load: p.load(),
+                    available_parallelism: p.available_parallelism(),
+                    projected_parallelism: p.projected_parallelism(),
+                    time_left: p.time_left(),
+                };
+                (p.name(), metrics)
+            })
+            .collect();
+        if self.next_sample == now {
+            self.samples.push((now, self.last.clone()));
+            self.next_sample += self.period;
+        }
+    }
+
+    /// Format the samples as CSV: one row per sample, four columns per producer. Cells are empty
+    /// when the producer didn't exist yet, or when its time left is unknown.
+    pub fn to_csv(&self) -> String {
+        let names = self
+            .samples
+            .iter()
+            .flat_map(|(_, sample)| sample.keys())
+            .unique()
+            .collect_vec();
+        let header = std::iter::once("tick".to_string())
+            .chain(names.iter().flat_map(|name| {
+                [
+                    "load",
+                    "available_parallelism",
+                    "projected_parallelism",
+                    "time_left",
+                ]
+                .map(|column| csv_escape(&format!("{name} {column}")))
+            }))
+            .join(",");
+        let rows = self.samples.iter().map(|(tick, sample)| {
+            std::iter::once(tick.to_string())
+                .chain(names.iter().flat_map(|name| match sample.get(*name) {
+                    Some(m) => [
+                        m.load.to_string(),
+                        m.available_parallelism.to_string(),
+                        m.projected_parallelism.to_string(),
+                        m.time_left.map(|t| t.to_string()).unwrap_or_default(),
+                    ],
+                    None => Default::default(),
+                }))
+                .join(",")
+        });
+        std::iter::once(header)
+            .chain(rows)
+            .map(|line| line + "\n")
+            .collect()
+    }
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl GameState {
+    /// Write the metrics time series to `path` as CSV.
+    pub fn write_metrics(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.metrics.to_csv())
+    }
+}
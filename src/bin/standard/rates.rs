@@ -0,0 +1,144 @@
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::*;
+
+/// What one producer must do to sustain the target rate of a `RatePlan`.
+pub struct ProducerRate {
+    /// See `Producer::name`.
+    pub name: String,
+    /// The entity we build to scale the producer up. `None` if there is nothing to build.
+    pub entity: Option<String>,
+    /// Number of items the producer must output per tick.
+    pub items_per_tick: Ratio,
+    /// Number of entities it takes to output that many items, if they never wait for inputs.
+    pub entities: Ratio,
+    /// See `Producer::max_parallelism`.
+    pub max_entities: u32,
+}
+
+impl ProducerRate {
+    /// Number of whole entities to build.
+    pub fn entities_needed(&self) -> u64 {
+        self.entities.ceil()
+    }
+    /// Whether `max_entities` is too few to sustain the rate.
+    pub fn is_capped(&self) -> bool {
+        self.entities > Ratio::from(self.max_entities)
+    }
+}
+
+/// The producing entities needed to make an item at a steady rate, see
+/// `GameState::machine_ratios`.
+pub struct RatePlan {
+    pub target: String,
+    /// Number of targets per tick.
+    pub rate: Ratio,
+    /// The producers involved, from the target down.
+    pub producers: Vec<ProducerRate>,
+}
+
+impl RatePlan {
+    /// The highest target rate that the limits on the number of entities allow, e.g. the
+    /// maximum number of miners on a territory. `None` if nothing limits it.
+    pub fn max_rate(&self) -> Option<Ratio> {
+        self.producers
+            .iter()
+            .filter(|p| p.max_entities != u32::MAX && !p.entities.is_zero())
+            .map(|p| self.rate * Ratio::from(p.max_entities) / p.entities)
+            .min()
+    }
+}
+
+impl std::fmt::Display for RatePlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {} per tick:", self.rate, self.target)?;
+        let header = [
+            "producer",
+            "entity",
+            "items/tick",
+            "entities",
+            "needed",
+            "max",
+        ];
+        let rows = std::iter::once(header.map(str::to_string))
+            .chain(self.producers.iter().map(|p| {
+                let max = if p.max_entities == u32::MAX {
+                    "-".to_string()
+                } else {
+                    p.max_entities.to_string()
+                };
+                [
+                    p.name.clone(),
+                    p.entity.clone().unwrap_or_else(|| "-".to_string()),
+                    p.items_per_tick.to_string(),
+                    p.entities.to_string(),
+                    p.entities_needed().to_string(),
+                    max,
+                ]
+            }))
+            .map(|row| row.map(|cell| format!("{cell}  ")))
+            .collect_vec();
+        writeln!(f, "{}", format_in_columns(&rows))?;
+        for p in self.producers.iter().filter(|p| p.is_capped()) {
+            writeln!(
+                f,
+                "{} needs {} entities but can have at most {}",
+                p.name,
+                p.entities_needed(),
+                p.max_entities
+            )?;
+        }
+        if let Some(max_rate) = self.max_rate() {
+            writeln!(f, "at most {max_rate} {} per tick", self.target)?;
+        }
+        Ok(())
+    }
+}
+
+impl GameState {
+    /// The producing entities it takes to make `rate` `T`s per tick, assuming every entity is
+    /// always busy. Unlocks are made once so they don't count. The limits on the number of
+    /// entities (e.g. `Territory::max_miners`) are those of the current game.
+    pub fn machine_ratios<T: BundleMakeable>(&mut self, rate: Ratio) -> RatePlan {
+        let mut graph = ResourceGraph::default();
+        T::add_node_to_graph(&mut graph);
+        let target = ResourceGraph::node_for::<T>();
+        // Edges to unlocks and crafting entities have weight zero.
+        let topo_sort = graph.topo_sort([target], |_, w| !w.is_zero());
+        let mut rates: HashMap<GraphNode, Ratio> = HashMap::from([(target, rate)]);
+        let mut producers: IndexMap<TypeId, ProducerRate> = IndexMap::new();
+        for node in topo_sort {
+            let items_per_tick = rates[&node];
+            for (tgt, w) in graph.edges(node).filter(|(_, w)| !w.is_zero()) {
+                *rates.entry(tgt).or_default() += items_per_tick * w;
+            }
+            let NodeKind::Produced(info) = graph.kind(node) else {
+                continue;
+            };
+            let max_entities = self
+                .producers
+                .get(info.producer)
+                .map_or(u32::MAX, |p| p.max_parallelism());
+            let entities = items_per_tick * Ratio::new(info.craft_time, info.output_amount as u64);
+            let p = producers
+                .entry(info.producer)
+                .or_insert_with(|| ProducerRate {
+                    name: info.name.clone(),
+                    entity: graph
+                        .contains(info.entity)
+                        .then(|| graph.name(info.entity).to_string()),
+                    items_per_tick: Ratio::ZERO,
+                    entities: Ratio::ZERO,
+                    max_entities,
+                });
+            p.items_per_tick += items_per_tick;
+            p.entities += entities;
+        }
+        RatePlan {
+            target: type_name::<T>(),
+            rate,
+            producers: producers.into_values().collect(),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::{Add, AddAssign, Div, Mul},
+    str::FromStr,
+};
+
+/// An exact non-negative fraction, for quantities in the resource graph. Always in lowest terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ratio {
+    num: u64,
+    den: u64,
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+impl Ratio {
+    pub const ZERO: Ratio = Ratio { num: 0, den: 1 };
+    pub const ONE: Ratio = Ratio { num: 1, den: 1 };
+
+    pub fn new(num: u64, den: u64) -> Self {
+        assert!(den != 0, "zero denominator");
+        let g = gcd(num, den);
+        Self {
+            num: num / g,
+            den: den / g,
+        }
+    }
+
+    pub fn numer(self) -> u64 {
+        self.num
+    }
+    pub fn denom(self) -> u64 {
+        self.den
+    }
+    pub fn is_zero(self) -> bool {
+        self.num == 0
+    }
+    pub fn is_integer(self) -> bool {
+        self.den == 1
+    }
+    /// The smallest integer that is at least `self`.
+    pub fn ceil(self) -> u64 {
+        self.num.div_ceil(self.den)
+    }
+    pub fn to_f64(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+impl Default for Ratio {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl From<u32> for Ratio {
+    fn from(n: u32) -> Self {
+        Self::from(n as u64)
+    }
+}
+
+impl From<u64> for Ratio {
+    fn from(n: u64) -> Self {
+        Self { num: n, den: 1 }
+    }
+}
+
+impl Add for Ratio {
+    type Output = Ratio;
+    fn add(self, rhs: Ratio) -> Ratio {
+        let g = gcd(self.den, rhs.den);
+        let den = self.den / g * rhs.den;
+        Ratio::new(self.num * (den / self.den) + rhs.num * (den / rhs.den), den)
+    }
+}
+
+impl AddAssign for Ratio {
+    fn add_assign(&mut self, rhs: Ratio) {
+        *self = *self + rhs;
+    }
+}
+
+impl Mul for Ratio {
+    type Output = Ratio;
+    fn mul(self, rhs: Ratio) -> Ratio {
+        // Cross-reduce first to keep the intermediate products small.
+        let a = Ratio::new(self.num, rhs.den);
+        let b = Ratio::new(rhs.num, self.den);
+        Ratio {
+            num: a.num * b.num,
+            den: a.den * b.den,
+        }
+    }
+}
+
+impl Mul<u32> for Ratio {
+    type Output = Ratio;
+    fn mul(self, rhs: u32) -> Ratio {
+        self * Ratio::from(rhs)
+    }
+}
+
+impl Div for Ratio {
+    type Output = Ratio;
+    fn div(self, rhs: Ratio) -> Ratio {
+        assert!(!rhs.is_zero(), "division by zero");
+        self * Ratio {
+            num: rhs.den,
+            den: rhs.num,
+        }
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.num as u128 * other.den as u128).cmp(&(other.num as u128 * self.den as u128))
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+impl FromStr for Ratio {
+    type Err = String;
+    /// Parses `n` or `n/d`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let int = |s: &str| {
+            s.trim()
+                .parse::<u64>()
+                .map_err(|e| format!("invalid number `{s}`: {e}"))
+        };
+        match s.split_once('/') {
+            None => Ok(Ratio::from(int(s)?)),
+            Some((num, den)) => match int(den)? {
+                0 => Err("zero denominator".to_string()),
+                den => Ok(Ratio::new(int(num)?, den)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stays_in_lowest_terms() {
+        let r = Ratio::new(6, 4);
+        assert_eq!((r.numer(), r.denom()), (3, 2));
+        assert_eq!(Ratio::new(0, 7), Ratio::ZERO);
+        assert!(Ratio::new(8, 4).is_integer());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let (half, third) = (Ratio::new(1, 2), Ratio::new(1, 3));
+        assert_eq!(half + third, Ratio::new(5, 6));
+        assert_eq!(half * third, Ratio::new(1, 6));
+        assert_eq!(half / third, Ratio::new(3, 2));
+        assert_eq!(Ratio::new(2, 3) * 3, Ratio::from(2u32));
+        let mut sum = Ratio::ZERO;
+        for _ in 0..3 {
+            sum += third;
+        }
+        assert_eq!(sum, Ratio::ONE);
+        assert_eq!(Ratio::new(7, 3).ceil(), 3);
+        assert_eq!(Ratio::new(6, 3).ceil(), 2);
+    }
+
+    #[test]
+    fn cross_reduces_large_products() {
+        let big = Ratio::new(u64::MAX / 3, 7);
+        assert_eq!(big * Ratio::new(7, u64::MAX / 3), Ratio::ONE);
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(Ratio::new(1, 3) < Ratio::new(1, 2));
+        assert!(Ratio::new(u64::MAX, u64::MAX - 1) > Ratio::ONE);
+        assert_eq!(Ratio::new(2, 4).cmp(&Ratio::new(1, 2)), Ordering::Equal);
+    }
+
+    #[test]
+    fn display_and_parse() {
+        assert_eq!(Ratio::new(3, 6).to_string(), "1/2");
+        assert_eq!(Ratio::from(4u32).to_string(), "4");
+        assert_eq!("3".parse(), Ok(Ratio::from(3u32)));
+        assert_eq!(" 2 / 4 ".parse(), Ok(Ratio::new(1, 2)));
+        assert_eq!("1/0".parse::<Ratio>(), Err("zero denominator".to_string()));
+        assert!("x/2".parse::<Ratio>().is_err());
+    }
+}
@@ -45,41 +45,36 @@ impl<R1: ResourceType, const N1: u32> MultiBundle for Bundle<R1, N1> {
         Some(res.0.bundle().ok()?)
     }
 }
-impl<R1: ResourceType, const N1: u32> MultiBundle for (Bundle<R1, N1>,) {
-    type AsTuple = Self;
-    type AsResource = (Resource<R1>,);
+macro_rules! impl_multi_bundle_tuple {
+    ($($R:ident $N:ident $i:tt),+) => {
+        impl<$($R: ResourceType, const $N: u32),+> MultiBundle for ($(Bundle<$R, $N>,)+) {
+            type AsTuple = Self;
+            type AsResource = ($(Resource<$R>,)+);
 
-    fn bundle_count(res: &Self::AsResource) -> u32 {
-        res.0.amount() / N1
-    }
-    fn add(res: &mut Self::AsResource, bundle: Self) {
-        res.0 += bundle.0;
-    }
-    fn bundle(res: &mut Self::AsResource) -> Option<Self> {
-        Some((res.0.bundle().ok()?,))
-    }
-}
-impl<R1: ResourceType, const N1: u32, R2: ResourceType, const N2: u32> MultiBundle
-    for (Bundle<R1, N1>, Bundle<R2, N2>)
-{
-    type AsTuple = Self;
-    type AsResource = (Resource<R1>, Resource<R2>);
-
-    fn bundle_count(res: &Self::AsResource) -> u32 {
-        std::cmp::min(res.0.amount() / N1, res.1.amount() / N2)
-    }
-    fn add(res: &mut Self::AsResource, bundle: Self) {
-        res.0 += bundle.0;
-        res.1 += bundle.1;
-    }
-    fn bundle(res: &mut Self::AsResource) -> Option<Self> {
-        if res.0.amount() >= N1 && res.1.amount() >= N2 {
-            Some((res.0.bundle().ok()?, res.1.bundle().ok()?))
-        } else {
-            None
+            fn bundle_count(res: &Self::AsResource) -> u32 {
+                u32::MAX$(.min(res.$i.amount() / $N))+
+            }
+            fn add(res: &mut Self::AsResource, bundle: Self) {
+                $(res.$i += bundle.$i;)+
+            }
+            fn bundle(res: &mut Self::AsResource) -> Option<Self> {
+                if true $(&& res.$i.amount() >= $N)+ {
+                    Some(($(res.$i.bundle().ok()?,)+))
+                } else {
+                    None
+                }
+            }
         }
-    }
+    };
 }
+impl_multi_bundle_tuple!(R1 N1 0);
+impl_multi_bundle_tuple!(R1 N1 0, R2 N2 1);
+impl_multi_bundle_tuple!(R1 N1 0, R2 N2 1, R3 N3 2);
+impl_multi_bundle_tuple!(R1 N1 0, R2 N2 1, R3 N3 2, R4 N4 3);
+impl_multi_bundle_tuple!(R1 N1 0, R2 N2 1, R3 N3 2, R4 N4 3, R5 N5 4);
+impl_multi_bundle_tuple!(R1 N1 0, R2 N2 1, R3 N3 2, R4 N4 3, R5 N5 4, R6 N6 5);
+impl_multi_bundle_tuple!(R1 N1 0, R2 N2 1, R3 N3 2, R4 N4 3, R5 N5 4, R6 N6 5, R7 N7 6);
+impl_multi_bundle_tuple!(R1 N1 0, R2 N2 1, R3 N3 2, R4 N4 3, R5 N5 4, R6 N6 5, R7 N7 6, R8 N8 7);
 
 /// Trait to compute statically-counted inputs and outputs.
 pub trait ConstRecipe: Recipe + Any {
@@ -0,0 +1,109 @@
+use std::io::{self, Write};
+
+use itertools::Itertools;
+use serde_json::json;
+
+use crate::*;
+
+/// Receives periodic snapshots of the producers, see `GameState::report_loads`.
+pub trait Reporter {
+    /// `loads` is sorted by producer name.
+    fn report(&mut self, tick: &Tick, loads: &[ProducerLoad]);
+}
+
+/// Prints a table to stderr.
+pub struct TextReporter;
+
+impl Reporter for TextReporter {
+    fn report(&mut self, tick: &Tick, loads: &[ProducerLoad]) {
+        let loads = loads
+            .iter()
+            .map(|l| {
+                let ProducerLoad {
+                    name,
+                    load,
+                    starving,
+                    parallelism,
+                    scaling_up,
+                    time_left,
+                    detail,
+                } = l;
+                let scaling_up = if *scaling_up == 0 {
+                    String::new()
+                } else {
+                    format!("+{scaling_up}")
+                };
+                let starving = if *starving == 0 {
+                    String::new()
+                } else {
+                    format!(" ({starving} starving)")
+                };
+                let time_left = time_left.map(|t| t as f32).unwrap_or(f32::INFINITY);
+                let report = if let Some(s) = detail {
+                    format!(" -- {s}")
+                } else {
+                    String::new()
+                };
+
+                [
+                    format!(" - {name}"),
+                    format!("  x{parallelism}{scaling_up}"),
+                    format!("   {load} items{starving}"),
+                    // format!("   {craft_time}s"),
+                    // format!(" per item   {time_left}s"),
+                    format!("   {time_left}s"),
+                    format!(" left   {report}"),
+                ]
+            })
+            .collect_vec();
+        let loads = format_in_columns(&loads);
+        eprintln!("{tick}:\n{loads}\n");
+    }
+}
+
+/// Writes one JSON object per snapshot and per line.
+pub struct JsonLinesReporter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonLinesReporter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl JsonLinesReporter<io::Stderr> {
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+}
+
+impl<W: Write> Reporter for JsonLinesReporter<W> {
+    fn report(&mut self, tick: &Tick, loads: &[ProducerLoad]) {
+        let producers = loads
+            .iter()
+            .map(|l| {
+                json!({
+                    "name": l.name,
+                    "load": l.load,
+                    "starving": l.starving,
+                    "parallelism": l.parallelism,
+                    "scaling_up": l.scaling_up,
+                    "time_left": l.time_left,
+                    "detail": l.detail,
+                })
+            })
+            .collect_vec();
+        let line = json!({ "tick": tick.cur(), "producers": producers });
+        if let Err(e) = writeln!(self.out, "{line}") {
+            eprintln!("could not write report: {e}");
+        }
+    }
+}
+
+/// Reports nothing.
+pub struct SilentReporter;
+
+impl Reporter for SilentReporter {
+    fn report(&mut self, _tick: &Tick, _loads: &[ProducerLoad]) {}
+}
@@ -1,9 +1,14 @@
+use std::cell::RefCell;
+
+use indexmap::IndexMap;
+
 use crate::*;
 
 /// A store of various resources.
 #[derive(Default)]
 pub struct Resources {
     any: HashMap<TypeId, Box<dyn Any>>,
+    pools: IndexMap<TypeId, Box<dyn ErasedPool>>,
 }
 
 impl Resources {
@@ -16,8 +21,38 @@ impl Resources {
         storage.downcast_mut().unwrap()
     }
 
+    /// The stock of this resource. Adding to it directly doesn't serve the waiters: use
+    /// `ResourceWithQueue::add`, or `Salvage` for items put back.
     pub fn resource<R: ResourceType + Any>(&mut self) -> &mut Resource<R> {
-        self.or_insert_any(|| Resource::<R>::new_empty())
+        &mut self.pool::<R>().resource
+    }
+    pub fn pool<R: ResourceType + Any>(&mut self) -> &mut ResourceWithQueue<R> {
+        let storage: &mut (dyn ErasedPool + 'static) = self
+            .pools
+            .entry(TypeId::of::<R>())
+            .or_insert_with(|| Box::new(ResourceWithQueue::<R>::new()))
+            .as_mut();
+        let storage: &mut (dyn Any + 'static) = storage;
+        storage.downcast_mut().unwrap()
+    }
+    pub fn iter_pools(&mut self) -> impl Iterator<Item = &mut dyn ErasedPool> {
+        self.pools.values_mut().map(|p| p.as_mut())
+    }
+    /// Hand out the stock of every pool to its waiters. Returns the number of waiters served.
+    pub fn serve_pools(&mut self, waiters: &mut CallBackQueue, tasks: &Tasks) -> usize {
+        self.iter_pools().map(|p| p.serve(waiters, tasks)).sum()
+    }
+    /// Sort all the pool queues again, e.g. after some priorities were raised.
+    pub fn resort(&mut self, aging: Aging) {
+        for p in self.iter_pools() {
+            p.resort(aging);
+        }
+    }
+    /// Drop the pool waiters that belong to cancelled requests.
+    pub fn remove_cancelled(&mut self, tasks: &Tasks) {
+        for p in self.iter_pools() {
+            p.remove_cancelled(tasks);
+        }
     }
     pub fn reusable<T: Reusable + Any>(&mut self) -> &mut ReusableContainer<T> {
         self.or_insert_any(|| ReusableContainer::empty())
@@ -67,67 +102,274 @@ impl<T: Reusable> ReusableContainer<T> {
     }
 }
 
-// /// A resource along with a queue of sinks waiting for that resource.
-// pub struct ResourceWithQueue<R: ResourceType> {
-//     resource: Resource<R>,
-//     /// Keep sorted by priority.
-//     queue: VecDeque<ResourceWaiter<R>>,
-// }
-
-// struct ResourceWaiter<R: ResourceType> {
-//     sink: Sink<Resource<R>>,
-//     /// The quantity of resource expected.
-//     quantity: u32,
-//     priority: Priority,
-// }
-
-// impl<R: ResourceType + Any> ResourceWithQueue<R> {
-//     pub fn new() -> Self {
-//         Self {
-//             resource: Resource::new_empty(),
-//             queue: Default::default(),
-//         }
-//     }
-
-//     /// Add some resource to the pool.
-//     pub fn add<const N: u32>(&mut self, waiters: &mut CallBackQueue, bundle: Bundle<R, N>) {
-//         self.resource.add(bundle);
-//         while let Some(w) = self.queue.front()
-//             && let Ok(bundle) = self.resource.split_off(w.quantity)
-//         {
-//             let w = self.queue.pop_front().unwrap();
-//             w.sink.give(waiters, bundle);
-//         }
-//     }
-
-//     /// Add some resource to the pool without trying to wake the waiters.
-//     pub fn add_no_wake<const N: u32>(&mut self, bundle: Bundle<R, N>) {
-//         self.resource.add(bundle);
-//     }
-
-//     pub fn bundle<const N: u32>(&mut self) -> Result<Bundle<R, N>, InsufficientResourceError<R>> {
-//         self.resource.bundle()
-//     }
-
-//     pub fn wait_for_bundle<const N: u32>(
-//         &mut self,
-//         waiters: &mut CallBackQueue,
-//         sink: Sink<Bundle<R, N>>,
-//         p: Priority,
-//     ) {
-//         if self.queue.is_empty()
-//             && let Ok(bundle) = self.resource.bundle()
-//         {
-//             sink.give(waiters, bundle);
-//         } else {
-//             self.queue.push_back(ResourceWaiter {
-//                 sink: sink.map(|_, mut res: Resource<R>| res.bundle().unwrap()),
-//                 quantity: N,
-//                 priority: p,
-//             });
-//             self.queue
-//                 .make_contiguous()
-//                 .sort_by_key(|w| Reverse(w.priority));
-//         }
-//     }
-// }
+/// Items that can be put back into `Resources` when they turn out not to be needed. Go through
+/// `Salvage` rather than calling `restock` directly: `GameState::restock_salvaged` then serves the
+/// pool waiters.
+pub trait Restock {
+    fn restock(self, resources: &mut Resources);
+}
+
+impl<const N: u32, R: ResourceType + Any> Restock for Bundle<R, N> {
+    fn restock(self, resources: &mut Resources) {
+        resources.resource().add(self.to_resource());
+    }
+}
+impl<R: ResourceType + Any> Restock for Resource<R> {
+    fn restock(self, resources: &mut Resources) {
+        resources.resource().add(self);
+    }
+}
+impl<T: Reusable> Restock for Available<T> {
+    // The item never left its container.
+    fn restock(self, _resources: &mut Resources) {}
+}
+impl Restock for () {
+    fn restock(self, _resources: &mut Resources) {}
+}
+macro_rules! impl_restock_tuple {
+    ($($T:ident $i:tt),+) => {
+        impl<$($T: Restock),+> Restock for ($($T,)+) {
+            fn restock(self, resources: &mut Resources) {
+                $(self.$i.restock(resources);)+
+            }
+        }
+    };
+}
+impl_restock_tuple!(A 0);
+impl_restock_tuple!(A 0, B 1);
+impl_restock_tuple!(A 0, B 1, C 2);
+impl_restock_tuple!(A 0, B 1, C 2, D 3);
+impl_restock_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_restock_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_restock_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_restock_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+impl<const N: usize, T: Restock> Restock for [T; N] {
+    fn restock(self, resources: &mut Resources) {
+        for x in self {
+            x.restock(resources);
+        }
+    }
+}
+impl<T: Restock> Restock for Vec<T> {
+    fn restock(self, resources: &mut Resources) {
+        for x in self {
+            x.restock(resources);
+        }
+    }
+}
+impl<T: Restock> Restock for Option<T> {
+    fn restock(self, resources: &mut Resources) {
+        if let Some(x) = self {
+            x.restock(resources);
+        }
+    }
+}
+
+/// Puts a salvaged value back into `Resources`.
+type Restocker = Box<dyn FnOnce(&mut Resources)>;
+
+thread_local! {
+    /// Values salvaged by `Salvage`, waiting to be put back into `Resources`.
+    static SALVAGED: RefCell<Vec<Restocker>> = Default::default();
+}
+
+/// Values that may get dropped halfway through a request, e.g. when it gets cancelled. Those that
+/// can be restocked are set aside, to be put back into `Resources` by
+/// `GameState::restock_salvaged`. The others are lost.
+pub trait Salvage {
+    fn salvage(self);
+}
+impl<T> Salvage for T {
+    default fn salvage(self) {}
+}
+impl<T: Restock + 'static> Salvage for T {
+    fn salvage(self) {
+        SALVAGED.with_borrow_mut(|salvaged| salvaged.push(Box::new(|r| self.restock(r))));
+    }
+}
+
+impl GameState {
+    /// Put salvaged values back into `Resources`, and serve the pool waiters that the restocked
+    /// resources satisfy. Returns whether any waiter was served.
+    pub fn restock_salvaged(&mut self) -> bool {
+        for f in SALVAGED.take() {
+            f(&mut self.resources);
+        }
+        self.resources.serve_pools(&mut self.queue, &self.tasks) != 0
+    }
+}
+
+/// A resource along with a queue of sinks waiting for some of that resource.
+pub struct ResourceWithQueue<R: ResourceType> {
+    pub resource: Resource<R>,
+    /// Ordered by priority, see `Aging::sort_key`.
+    queue: WaiterQueue<ResourceWaiter<R>>,
+}
+
+struct ResourceWaiter<R: ResourceType> {
+    /// The quantity of resource expected.
+    quantity: u32,
+    waiter: Waiter<Resource<R>>,
+}
+
+impl<R: ResourceType + Any> Default for ResourceWithQueue<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: ResourceType + Any> ResourceWithQueue<R> {
+    pub fn new() -> Self {
+        Self {
+            resource: Resource::new_empty(),
+            queue: Default::default(),
+        }
+    }
+
+    /// Add some resource to the pool and serve the waiters that it satisfies.
+    pub fn add(&mut self, waiters: &mut CallBackQueue, tasks: &Tasks, resource: Resource<R>) {
+        self.resource.add(resource);
+        self.serve(waiters, tasks);
+    }
+
+    /// Take a bundle out of the pool if nobody is waiting before us.
+    pub fn bundle<const N: u32>(&mut self) -> Option<Bundle<R, N>> {
+        if self.queue.is_empty() {
+            self.resource.bundle().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Wait for `quantity` of the resource to be in the pool.
+    pub fn wait(
+        &mut self,
+        waiters: &mut CallBackQueue,
+        tasks: &Tasks,
+        aging: Aging,
+        quantity: u32,
+        waiter: Waiter<Resource<R>>,
+    ) {
+        let key = aging.sort_key(waiter.priority.get(), waiter.since);
+        self.queue.push(key, ResourceWaiter { quantity, waiter });
+        // We may now be first in line for the current stock.
+        self.serve(waiters, tasks);
+    }
+
+    /// Hand out the stock to waiters in priority order, stopping at the first one we can't
+    /// satisfy. Returns the number of waiters served.
+    pub fn serve(&mut self, waiters: &mut CallBackQueue, tasks: &Tasks) -> usize {
+        let mut served = 0;
+        loop {
+            // Callbacks may have enqueued waiters for requests that were already cancelled.
+            while self
+                .queue
+                .peek()
+                .is_some_and(|w| w.waiter.is_cancelled(tasks))
+            {
+                self.queue.pop();
+            }
+            let Some(w) = self.queue.peek() else {
+                break;
+            };
+            let Ok(resource) = self.resource.split_off(w.quantity) else {
+                break;
+            };
+            let w = self.queue.pop().unwrap();
+            w.waiter.sink.give(waiters, resource);
+            served += 1;
+        }
+        served
+    }
+
+    /// Drop the waiters that belong to cancelled requests. Returns how many were removed.
+    pub fn remove_cancelled(&mut self, tasks: &Tasks) -> usize {
+        let len = self.queue.len();
+        self.queue.retain(|w| !w.waiter.is_cancelled(tasks));
+        len - self.queue.len()
+    }
+
+    /// Sort the queue again, e.g. after some priorities were raised.
+    pub fn resort(&mut self, aging: Aging) {
+        self.queue
+            .rekey(|w| aging.sort_key(w.waiter.priority.get(), w.waiter.since));
+    }
+}
+
+pub trait ErasedPool: Any {
+    /// Hand out the stock to waiters. Returns the number of waiters served.
+    fn serve(&mut self, waiters: &mut CallBackQueue, tasks: &Tasks) -> usize;
+    /// Drop the waiters that belong to cancelled requests. Returns how many were removed.
+    fn remove_cancelled(&mut self, tasks: &Tasks) -> usize;
+    /// Sort the queue again, e.g. after some priorities were raised.
+    fn resort(&mut self, aging: Aging);
+}
+impl<R: ResourceType + Any> ErasedPool for ResourceWithQueue<R> {
+    fn serve(&mut self, waiters: &mut CallBackQueue, tasks: &Tasks) -> usize {
+        self.serve(waiters, tasks)
+    }
+    fn remove_cancelled(&mut self, tasks: &Tasks) -> usize {
+        self.remove_cancelled(tasks)
+    }
+    fn resort(&mut self, aging: Aging) {
+        self.resort(aging)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::test_utils::with_state;
+
+    /// A waiter that logs the amount it gets under `name`.
+    fn waiter(
+        log: &Rc<RefCell<Vec<(&'static str, u32)>>>,
+        name: &'static str,
+        task: Option<TaskId>,
+        p: u16,
+    ) -> Waiter<Resource<Iron>> {
+        let log = log.clone();
+        Waiter {
+            sink: Sink::from_fn(move |_, r: Resource<Iron>| {
+                log.borrow_mut().push((name, r.amount()))
+            }),
+            task,
+            priority: SharedPriority::new(Priority(p)),
+            since: 0,
+        }
+    }
+
+    #[test]
+    fn pool_serves_the_head_of_the_line_first() {
+        with_state(|mut state| {
+            let iron = state.resources.resource::<Iron>();
+            let mut take = |n| iron.split_off(n).unwrap();
+            let (mut queue, mut tasks) = (CallBackQueue::default(), Tasks::default());
+            let log = Rc::new(RefCell::new(vec![]));
+            let mut pool = ResourceWithQueue::<Iron>::new();
+            let aging = Aging::default();
+
+            let cancelled = tasks.start("cancelled".to_string(), 0, Priority(2));
+            let w = waiter(&log, "cancelled", Some(cancelled), 2);
+            pool.wait(&mut queue, &tasks, aging, 1, w);
+            tasks.cancel(cancelled, 0);
+            let w = waiter(&log, "low", None, 0);
+            pool.wait(&mut queue, &tasks, aging, 2, w);
+            let w = waiter(&log, "high", None, 1);
+            pool.wait(&mut queue, &tasks, aging, 5, w);
+            assert!(pool.bundle::<1>().is_none());
+
+            // Enough for `low`, but `high` is first in line.
+            pool.add(&mut queue, &tasks, take(3));
+            assert!(log.borrow().is_empty());
+            pool.add(&mut queue, &tasks, take(2));
+            assert_eq!(*log.borrow(), [("high", 5)]);
+            pool.add(&mut queue, &tasks, take(3));
+            assert_eq!(*log.borrow(), [("high", 5), ("low", 2)]);
+            assert_eq!(pool.resource.amount(), 1);
+            assert!(pool.bundle::<1>().is_some());
+        });
+    }
+}
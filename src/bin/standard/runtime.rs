@@ -1,9 +1,9 @@
 use std::{
     any::Any,
+    fmt::Display,
     ops::{ControlFlow, Deref},
 };
 
-use itertools::Itertools;
 use rustorio::Tick;
 
 use crate::*;
@@ -36,13 +36,36 @@ impl<T> Deref for RestrictMut<T> {
     }
 }
 
+/// Default number of ticks between two load reports.
+pub const DEFAULT_REPORT_PERIOD: u64 = 100;
+/// Default number of ticks `complete` may spend waiting on a single handle.
+pub const DEFAULT_TICK_BUDGET: u64 = 10000;
+/// Default number of ticks without any progress after which we consider the game stuck.
+pub const DEFAULT_STALL_TICKS: u64 = 1000;
+
 pub struct GameState {
     pub tick: RestrictMut<Tick>,
     last_reported_tick: u64,
+    /// Number of ticks between two calls to `reporter`.
+    pub report_period: u64,
+    /// Where load reports go.
+    pub reporter: Box<dyn Reporter>,
+    /// Number of ticks `complete` may spend waiting on a single handle.
+    pub tick_budget: u64,
+    /// Number of ticks without any progress after which we consider the game stuck. We always
+    /// wait for at least the craft time of the slowest busy producer.
+    pub stall_ticks: u64,
+    /// Last tick at which something happened: an output was handed out, something was crafted by
+    /// hand, or a callback ran.
+    pub(crate) last_progress_tick: u64,
+    /// Nothing can happen before this tick, so `tick_fwd` skips straight to it.
+    next_event_tick: u64,
     pub resources: Resources,
     pub producers: Producers,
     pub queue: CallBackQueue,
     pub graph: ResourceGraph,
+    pub tasks: Tasks,
+    pub metrics: Metrics,
 }
 
 impl GameState {
@@ -52,10 +75,18 @@ impl GameState {
         GameState {
             tick: RestrictMut::new(tick),
             last_reported_tick: 0,
+            report_period: DEFAULT_REPORT_PERIOD,
+            reporter: Box::new(TextReporter),
+            tick_budget: DEFAULT_TICK_BUDGET,
+            stall_ticks: DEFAULT_STALL_TICKS,
+            last_progress_tick: 0,
+            next_event_tick: 0,
             queue: Default::default(),
             resources,
             producers,
             graph: Default::default(),
+            tasks: Default::default(),
+            metrics: Metrics::new(DEFAULT_METRICS_PERIOD),
         }
     }
 
@@ -63,88 +94,160 @@ impl GameState {
         let mut_token = RestrictMutToken(()); // Only place where we create one.
 
         let tick_mut = self.tick.as_mut(mut_token);
-        match self
+        let crafted_by_hand = match self
             .producers
             .with_hand_producers(|p| p.craft_by_hand_if_needed(tick_mut))
         {
-            ControlFlow::Break(AdvancedTick) => {}
-            ControlFlow::Continue(()) => tick_mut.advance(),
-        }
+            ControlFlow::Break(AdvancedTick) => true,
+            ControlFlow::Continue(()) => {
+                tick_mut.advance();
+                while tick_mut.cur() < self.next_event_tick {
+                    tick_mut.advance();
+                }
+                false
+            }
+        };
 
-        self.check_waiters();
+        let progressed = self.check_waiters();
+        let now = self.tick.cur();
+        if crafted_by_hand || progressed {
+            self.last_progress_tick = now;
+        }
         self.report_loads();
+        self.metrics.record(now, &mut self.producers);
+
+        // Work out how far we can skip. If anything happened, callbacks may have changed some
+        // producers in ways we can't see, so we look again at the next tick.
+        let next_output_tick = self.producers.next_output_tick(now);
+        self.next_event_tick = if crafted_by_hand || progressed {
+            now + 1
+        } else {
+            // Don't skip over load reports.
+            let next_report_tick = (now / self.report_period + 1) * self.report_period;
+            next_output_tick.unwrap_or(now + 1).min(next_report_tick)
+        };
     }
 
-    pub fn check_waiters(&mut self) {
+    /// Returns whether anything happened.
+    pub fn check_waiters(&mut self) -> bool {
+        let mut progressed = self.restock_salvaged();
+        let now = self.tick.cur();
         let mut scale_ups = vec![];
         for m in self.producers.iter_producers() {
-            m.update(&self.tick, &mut self.queue);
+            if m.may_have_output(now) {
+                let delivered = m.update(&self.tick, &mut self.queue, &self.tasks);
+                if let Some((node, amount)) = self.graph.producer_node(m.producer_id()) {
+                    self.graph
+                        .record_production(node, delivered as u64 * amount as u64);
+                }
+                progressed |= delivered != 0;
+            }
+            if let Some((node, _)) = self.graph.producer_node(m.producer_id()) {
+                self.graph.record_waiting(node, m.load());
+            }
             if let Some(f) = m.scale_up_if_needed() {
                 scale_ups.push(f);
             }
         }
+        progressed |= !scale_ups.is_empty();
         for f in scale_ups.drain(..) {
             f(self);
         }
         while let Some(f) = self.queue.next_callback() {
+            progressed = true;
             f(self);
         }
+        progressed
     }
 
+    /// Hand a snapshot of the producers to `self.reporter` every `self.report_period` ticks.
     pub fn report_loads(&mut self) {
-        // const REPORT_PERIOD: u64 = 5;
-        const REPORT_PERIOD: u64 = 100;
-        if self.tick.cur() / REPORT_PERIOD == self.last_reported_tick / REPORT_PERIOD {
+        let period = self.report_period;
+        if self.tick.cur() / period == self.last_reported_tick / period {
             return;
         }
         self.last_reported_tick = self.tick.cur();
 
-        let r = &mut self.producers;
-        let loads = r
+        let loads = self.producers.loads(&self.tick);
+        self.reporter.report(&self.tick, &loads);
+    }
+
+    /// If nothing happened for a while, figure out why.
+    pub fn check_stalled(&mut self) -> Option<Deadlock> {
+        let longest_craft = self
+            .producers
             .iter_producers()
-            .sorted_by_key(|p| p.name())
-            .map(|p| {
-                let name = p.name();
-                let load = p.load();
-                let parallelism = p.available_parallelism();
-                let scaling_up = p.projected_parallelism() - parallelism;
-                let scaling_up = if scaling_up == 0 {
-                    String::new()
-                } else {
-                    format!("+{scaling_up}")
-                };
-                let time_left = p.time_left().map(|t| t as f32).unwrap_or(f32::INFINITY);
-                let report = if let Some(s) = p.report_load(&self.tick) {
-                    format!(" -- {s}")
-                } else {
-                    String::new()
-                };
+            .filter(|p| p.load() != 0)
+            .map(|p| p.craft_time())
+            .max()
+            .unwrap_or(0);
+        let threshold = self.stall_ticks.max(longest_craft);
+        (self.tick.cur() - self.last_progress_tick > threshold).then(|| self.diagnose_deadlock())
+    }
 
-                [
-                    format!(" - {name}"),
-                    format!("  x{parallelism}{scaling_up}"),
-                    format!("   {load} items"),
-                    // format!("   {craft_time}s"),
-                    // format!(" per item   {time_left}s"),
-                    format!("   {time_left}s"),
-                    format!(" left   {report}"),
-                ]
-            })
-            .collect_vec();
-        let loads = format_in_columns(&loads);
-        eprintln!("{}:\n{}\n", self.tick.as_ref(), loads);
+    /// Run the game until the handle resolves. Panics if that takes more than `self.tick_budget`
+    /// ticks or if we get stuck.
+    pub fn complete<R: Any>(&mut self, h: WakeHandle<R>) -> R {
+        match self.complete_within(h, self.tick_budget) {
+            Ok(ret) => ret,
+            Err(e) => panic!("{}", e.reason),
+        }
     }
 
-    pub fn complete<R: Any>(&mut self, mut h: WakeHandle<R>) -> R {
+    /// Run the game until the handle resolves, for at most `budget` ticks. On failure, hands back
+    /// the still-pending handle so the caller can change tactics and try again.
+    pub fn complete_within<R: Any>(
+        &mut self,
+        mut h: WakeHandle<R>,
+        budget: u64,
+    ) -> Result<R, CompleteError<R>> {
+        let start = self.tick.cur();
         let ControlFlow::Break(ret) = try {
             loop {
                 h = h.try_get()?;
                 self.tick_fwd();
-                if self.tick.cur() > 10000 {
-                    panic!("ticked too far?")
-                }
+                let reason = if let Some(deadlock) = self.check_stalled() {
+                    StopReason::Deadlock(deadlock)
+                } else if self.tick.cur() - start > budget {
+                    StopReason::OverBudget { budget }
+                } else {
+                    continue;
+                };
+                return Err(CompleteError {
+                    handle: h,
+                    reason,
+                    loads: self.producers.loads(&self.tick),
+                });
             }
         };
-        ret
+        Ok(ret)
+    }
+}
+
+/// Why we stopped waiting on a handle.
+pub enum StopReason {
+    /// We spent the whole tick budget.
+    OverBudget { budget: u64 },
+    /// Nothing happened for a while.
+    Deadlock(Deadlock),
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::OverBudget { budget } => {
+                write!(f, "went over the budget of {budget} ticks")
+            }
+            StopReason::Deadlock(deadlock) => write!(f, "the game is stuck: {deadlock}"),
+        }
     }
 }
+
+/// Error returned when a handle didn't resolve in time.
+pub struct CompleteError<R> {
+    /// The handle we were waiting on. It is still live and can be waited on again.
+    pub handle: WakeHandle<R>,
+    pub reason: StopReason,
+    /// The state of the producers when we stopped.
+    pub loads: Vec<ProducerLoad>,
+}
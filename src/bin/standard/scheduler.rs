@@ -1,4 +1,13 @@
-use std::{any::Any, cell::RefCell, collections::VecDeque, rc::Rc};
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::VecDeque,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use itertools::Itertools;
 
 use crate::*;
 
@@ -31,77 +40,114 @@ impl<T: Any> Sink<T, CallBackQueue> {
     }
 }
 
-impl<A: Any, B: Any, S: Any> Sink<(A, B), S> {
-    pub fn split(self) -> (Sink<A, S>, Sink<B, S>) {
-        struct PairinatorInner<A, B, S> {
-            a: Option<A>,
-            b: Option<B>,
-            sink: Sink<(A, B), S>,
+/// Storage for a value, which gets salvaged if it's dropped before being taken out.
+struct Salvaged<T: Any>(Option<T>);
+
+impl<T: Any> Salvaged<T> {
+    fn new(x: T) -> Self {
+        Self(Some(x))
+    }
+    fn empty() -> Self {
+        Self(None)
+    }
+    fn set(&mut self, x: T) {
+        self.0 = Some(x);
+    }
+    fn get_mut(&mut self) -> &mut T {
+        self.0.as_mut().unwrap()
+    }
+    fn take(&mut self) -> T {
+        self.0.take().unwrap()
+    }
+}
+impl<T: Any> Drop for Salvaged<T> {
+    fn drop(&mut self) {
+        if let Some(x) = self.0.take() {
+            x.salvage();
         }
+    }
+}
 
-        fn call_back_if_ready<A: Any, B: Any, S: Any>(
-            rc: Rc<RefCell<PairinatorInner<A, B, S>>>,
-            q: &mut S,
-        ) {
-            if let Some(inner) = Rc::into_inner(rc) {
-                let inner = RefCell::into_inner(inner);
-                inner.sink.give(q, (inner.a.unwrap(), inner.b.unwrap()))
+macro_rules! impl_split {
+    ($($T:ident $i:tt),+) => {
+        impl<$($T: Any,)+ S: Any> Sink<($($T,)+), S> {
+            /// Get one sink per tuple element. The original sink is called once all of them have
+            /// been given a value.
+            pub fn split(self) -> ($(Sink<$T, S>,)+) {
+                fn finish<$($T,)+ S>(
+                    mut values: ($(Salvaged<$T>,)+),
+                    sink: Sink<($($T,)+), S>,
+                    q: &mut S,
+                ) where
+                    $($T: Any,)+
+                    S: Any,
+                {
+                    sink.give(q, ($(values.$i.take(),)+))
+                }
+                // If the request is abandoned, the values that already arrived get salvaged.
+                let rc = Rc::new(RefCell::new((($(Salvaged::<$T>::empty(),)+), self)));
+                ($({
+                    let rc = rc.clone();
+                    Sink::from_fn(move |q, x: $T| {
+                        rc.borrow_mut().0.$i.set(x);
+                        if let Some(inner) = Rc::into_inner(rc) {
+                            let (values, sink) = RefCell::into_inner(inner);
+                            finish(values, sink, q)
+                        }
+                    })
+                },)+)
             }
         }
-        let rc = Rc::new(RefCell::new(PairinatorInner {
-            a: None,
-            b: None,
-            sink: self,
-        }));
-        let a_side = rc.clone();
-        let a = Sink::from_fn(|q, x| {
-            a_side.borrow_mut().a = Some(x);
-            call_back_if_ready(a_side, q);
-        });
-        let b_side = rc;
-        let b = Sink::from_fn(|q, x| {
-            b_side.borrow_mut().b = Some(x);
-            call_back_if_ready(b_side, q);
-        });
-        (a, b)
-    }
+    };
 }
+impl_split!(A 0, B 1);
+impl_split!(A 0, B 1, C 2);
+impl_split!(A 0, B 1, C 2, D 3);
+impl_split!(A 0, B 1, C 2, D 3, E 4);
+impl_split!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_split!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_split!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
 
 impl<const N: usize, T: Any, S: Any> Sink<[T; N], S> {
     pub fn split_n(self) -> [Sink<T, S>; N] {
-        let rc = Rc::new(RefCell::new((vec![], self)));
+        let rc = Rc::new(RefCell::new((Salvaged::new(vec![]), self)));
         std::array::from_fn(|_| {
             let rc = rc.clone();
             Sink::from_fn(move |q, x| {
-                rc.borrow_mut().0.push(x);
+                rc.borrow_mut().0.get_mut().push(x);
                 if let Some(inner) = Rc::into_inner(rc) {
-                    let inner = RefCell::into_inner(inner);
-                    inner.1.give(q, inner.0.try_into().ok().unwrap())
+                    let (mut items, sink) = RefCell::into_inner(inner);
+                    sink.give(q, items.take().try_into().ok().unwrap())
                 }
             })
         })
     }
 }
 
-impl<const COUNT: u32, R: ResourceType + Any, S: Any> Sink<Bundle<R, COUNT>, S> {
-    pub fn split_resource<B: IsBundle<Resource = R> + Any>(
-        self,
-    ) -> [Sink<B, S>; (COUNT / B::AMOUNT) as usize]
-    where
-        [(); (COUNT / B::AMOUNT) as usize]:,
-    {
-        assert_eq!(COUNT % B::AMOUNT, 0);
-        let rc = Rc::new(RefCell::new((Resource::new_empty(), Some(self))));
-        std::array::from_fn(|_| {
-            let rc = rc.clone();
-            Sink::from_fn(move |q, b: B| {
-                let mut inner = rc.borrow_mut();
-                inner.0.add(b.to_resource());
-                if inner.0.amount() >= COUNT {
-                    inner.1.take().unwrap().give(q, inner.0.bundle().unwrap())
-                }
+impl<T: Any, S: Any> Sink<Vec<T>, S> {
+    /// Like `split_n`, for a number of items only known at runtime. Takes `s` to be able to
+    /// resolve immediately if `n == 0`.
+    pub fn split_vec(self, s: &mut S, n: usize) -> Vec<Sink<T, S>> {
+        if n == 0 {
+            self.give(s, vec![]);
+            return vec![];
+        }
+        let rc = Rc::new(RefCell::new((
+            Salvaged::new(std::iter::repeat_with(|| None).take(n).collect_vec()),
+            self,
+        )));
+        (0..n)
+            .map(|i| {
+                let rc = rc.clone();
+                Sink::from_fn(move |q, x| {
+                    rc.borrow_mut().0.get_mut()[i] = Some(x);
+                    if let Some(inner) = Rc::into_inner(rc) {
+                        let (mut items, sink) = RefCell::into_inner(inner);
+                        sink.give(q, items.take().into_iter().map(Option::unwrap).collect())
+                    }
+                })
             })
-        })
+            .collect()
     }
 }
 
@@ -111,6 +157,10 @@ pub struct Source<T, S = CallBackQueue>(Rc<RefCell<SourceInner<T, S>>>);
 struct SourceInner<T, S> {
     sink: Option<Sink<T, S>>,
     value: Option<T>,
+    /// Set when the source is being awaited.
+    waker: Option<Waker>,
+    /// The request that will resolve this source, if known.
+    task: Option<TaskId>,
 }
 
 impl<T: Any, S: Any> Source<T, S> {
@@ -119,6 +169,8 @@ impl<T: Any, S: Any> Source<T, S> {
         let rc = Rc::new(RefCell::new(SourceInner {
             sink: None,
             value: None,
+            waker: None,
+            task: None,
         }));
         let source = Source(rc.clone());
         let sink = Sink::from_fn(move |s, x| {
@@ -130,6 +182,9 @@ impl<T: Any, S: Any> Source<T, S> {
                 sink.give(s, x);
             } else {
                 inner.value = Some(x);
+                if let Some(waker) = inner.waker.take() {
+                    waker.wake();
+                }
             }
         });
         (source, sink)
@@ -138,9 +193,18 @@ impl<T: Any, S: Any> Source<T, S> {
         let rc = Rc::new(RefCell::new(SourceInner {
             sink: None,
             value: Some(x),
+            waker: None,
+            task: None,
         }));
         Source(rc)
     }
+    /// The request that will resolve this source, if known.
+    pub fn task(&self) -> Option<TaskId> {
+        self.0.borrow().task
+    }
+    pub fn set_task(&self, id: TaskId) {
+        self.0.borrow_mut().task = Some(id);
+    }
     pub fn try_get(self) -> ControlFlow<T, Self> {
         let opt_value = self.0.borrow_mut().value.take();
         match opt_value {
@@ -160,6 +224,126 @@ impl<T: Any, S: Any> Source<T, S> {
         }
     }
 }
+/// Awaiting a source is an alternative to `set_sink`; the two must not be mixed. The future is
+/// woken when the value arrives, which for a `WakeHandle` requires the game to be running, see
+/// `GameState::block_on`.
+impl<T: Any, S: Any> Future for Source<T, S> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut inner = self.0.borrow_mut();
+        match inner.value.take() {
+            Some(val) => Poll::Ready(val),
+            None => {
+                inner.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T: Any, S: Any> Source<T, S> {
+    /// Transform the value once it arrives.
+    pub fn map<U: Any>(self, s: &mut S, f: impl FnOnce(&mut S, T) -> U + 'static) -> Source<U, S> {
+        let (source, sink) = Source::make_pipe();
+        if let Some(id) = self.task() {
+            source.set_task(id);
+        }
+        self.set_sink(s, sink.map(f));
+        source
+    }
+    /// Once the value arrives, use it to start something else and wait for that.
+    pub fn and_then<U: Any>(
+        self,
+        s: &mut S,
+        f: impl FnOnce(&mut S, T) -> Source<U, S> + 'static,
+    ) -> Source<U, S> {
+        self.map(s, f).flatten(s)
+    }
+    /// Wait for both values.
+    pub fn zip<U: Any>(self, s: &mut S, other: Source<U, S>) -> Source<(T, U), S> {
+        let (source, sink) = Source::<(T, U), S>::make_pipe();
+        let (a, b) = sink.split();
+        self.set_sink(s, a);
+        other.set_sink(s, b);
+        source
+    }
+    /// Look at the value as it passes through.
+    pub fn inspect(self, s: &mut S, f: impl FnOnce(&mut S, &T) + 'static) -> Source<T, S> {
+        self.map(s, |s, x| {
+            f(s, &x);
+            x
+        })
+    }
+
+    /// Wait for all the sources to resolve. The results are in the same order as `sources`.
+    pub fn join_all(s: &mut S, sources: Vec<Source<T, S>>) -> Source<Vec<T>, S> {
+        let (source, sink) = Source::make_pipe();
+        let sinks = sink.split_vec(s, sources.len());
+        for (source, sink) in sources.into_iter().zip(sinks) {
+            source.set_sink(s, sink);
+        }
+        source
+    }
+
+    /// Wait for the first source to resolve, and return its index along with its value. The
+    /// values of the other sources are passed to `leftover` as they come in. Panics if `sources`
+    /// is empty.
+    pub fn select_with(
+        s: &mut S,
+        sources: Vec<Source<T, S>>,
+        leftover: impl Fn(&mut S, usize, T) + 'static,
+    ) -> Source<(usize, T), S> {
+        assert!(!sources.is_empty(), "selecting among no sources");
+        let (source, sink) = Source::make_pipe();
+        let winner = Rc::new(RefCell::new(Some(sink)));
+        let leftover = Rc::new(leftover);
+        for (i, source) in sources.into_iter().enumerate() {
+            let winner = winner.clone();
+            let leftover = leftover.clone();
+            source.set_sink(
+                s,
+                Sink::from_fn(move |s, x| {
+                    let sink = winner.borrow_mut().take();
+                    match sink {
+                        Some(sink) => sink.give(s, (i, x)),
+                        None => leftover(s, i, x),
+                    }
+                }),
+            );
+        }
+        source
+    }
+}
+
+impl<T: Any> Source<T, GameState> {
+    /// Change the priority of the request behind this handle and of all its pending
+    /// sub-requests. Returns `None` if the handle isn't tied to a request, e.g. if it comes from
+    /// `make_pipe` or `zip`.
+    pub fn set_priority(&self, state: &mut GameState, p: Priority) -> Option<()> {
+        state.set_task_priority(self.task()?, p);
+        Some(())
+    }
+    /// Abandon the request behind this handle. Its waiters are removed from the producer queues
+    /// and the intermediate items already made go back to `state.resources`. Returns `None` if
+    /// the handle isn't tied to a request.
+    pub fn cancel(self, state: &mut GameState) -> Option<()> {
+        state.cancel_task(self.task()?);
+        Some(())
+    }
+}
+
+impl<T: Restock + Any> Source<T, GameState> {
+    /// Wait for the first source to resolve, and return its index along with its value. The
+    /// values of the other sources are put back into `state.resources` as they come in, where
+    /// they serve the pool waiters. Panics if `sources` is empty.
+    pub fn select(state: &mut GameState, sources: Vec<Self>) -> Source<(usize, T), GameState> {
+        Self::select_with(state, sources, |state, _, x| {
+            x.salvage();
+            state.restock_salvaged();
+        })
+    }
+}
+
 impl<T: Any, S: Any> Source<Source<T, S>, S> {
     pub fn flatten(self, s: &mut S) -> Source<T, S> {
         let (source, sink) = Source::make_pipe();
@@ -217,3 +401,107 @@ impl GameState {
         sink
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::with_state;
+
+    /// Pipes whose values get logged into the state as they come through.
+    fn pipes(n: usize) -> (Vec<Source<u32, Vec<u32>>>, Vec<Sink<u32, Vec<u32>>>) {
+        (0..n).map(|_| Source::make_pipe()).unzip()
+    }
+
+    #[test]
+    fn join_all_keeps_the_order_of_the_sources() {
+        let mut log = vec![];
+        let (sources, sinks) = pipes(3);
+        let joined = Source::join_all(&mut log, sources);
+        let mut sinks = sinks.into_iter().map(Some).collect_vec();
+        for i in [2, 0, 1] {
+            sinks[i].take().unwrap().give(&mut log, i as u32 * 10);
+        }
+        let ControlFlow::Break(values) = joined.try_get() else {
+            panic!("all the sources resolved")
+        };
+        assert_eq!(values, vec![0, 10, 20]);
+    }
+
+    #[test]
+    fn select_with_routes_the_winner_and_the_leftovers() {
+        let mut log = vec![];
+        let (sources, sinks) = pipes(3);
+        let selected =
+            Source::select_with(&mut log, sources, |log, i, x| log.push(i as u32 * 100 + x));
+        let mut sinks = sinks.into_iter().map(Some).collect_vec();
+        sinks[1].take().unwrap().give(&mut log, 7);
+        let ControlFlow::Break(winner) = selected.try_get() else {
+            panic!("the second source resolved")
+        };
+        assert_eq!(winner, (1, 7));
+        assert!(log.is_empty());
+        sinks[2].take().unwrap().give(&mut log, 8);
+        sinks[0].take().unwrap().give(&mut log, 9);
+        assert_eq!(log, vec![208, 9]);
+    }
+
+    #[test]
+    #[should_panic = "selecting among no sources"]
+    fn select_with_needs_sources() {
+        drop(Source::select_with(
+            &mut vec![],
+            vec![],
+            |_: &mut Vec<u32>, _, _: u32| {},
+        ));
+    }
+
+    #[test]
+    fn cancel_empties_the_queues_and_salvages() {
+        with_state(|mut state| {
+            let iron = state.resources.resource::<Iron>().amount();
+            // The iron comes straight from stock, the copper has to be smelted.
+            let h = state.make::<(Bundle<Iron, 4>, Bundle<Copper, 1>)>(Priority(0));
+            assert_eq!(state.resources.resource::<Iron>().amount(), iron - 4);
+            assert!(state.producers.iter_producers().any(|p| p.load() != 0));
+
+            assert_eq!(h.cancel(&mut state), Some(()));
+            assert!(state.producers.iter_producers().all(|p| p.load() == 0));
+            assert_eq!(state.resources.resource::<Iron>().amount(), iron);
+        });
+    }
+
+    #[test]
+    fn cancel_needs_a_request() {
+        with_state(|mut state| {
+            let (h, _sink) = WakeHandle::<Bundle<Iron, 1>>::make_pipe();
+            assert_eq!(h.set_priority(&mut state, Priority(1)), None);
+            assert_eq!(h.cancel(&mut state), None);
+        });
+    }
+
+    #[test]
+    fn select_puts_leftovers_back_into_the_pool() {
+        with_state(|mut state| {
+            let (sources, sinks): (Vec<_>, Vec<_>) = (0..3)
+                .map(|_| WakeHandle::<Bundle<Iron, 2>>::make_pipe())
+                .unzip();
+            let selected = Source::select(&mut state, sources);
+            let iron = state.resources.resource::<Iron>();
+            let bundles: [Bundle<Iron, 2>; 3] = std::array::from_fn(|_| iron.bundle().unwrap());
+            let _rest = iron.split_off(iron.amount()).unwrap();
+            // Can only be served once both leftovers are back.
+            let waiting = state.make::<Bundle<Iron, 4>>(Priority(0));
+
+            for (sink, bundle) in sinks.into_iter().zip(bundles) {
+                sink.give(&mut state, bundle);
+            }
+            let ControlFlow::Break((winner, _)) = selected.try_get() else {
+                panic!("the first source resolved")
+            };
+            assert_eq!(winner, 0);
+            state.check_waiters();
+            assert!(waiting.try_get().is_break());
+            assert_eq!(state.resources.resource::<Iron>().amount(), 0);
+        });
+    }
+}
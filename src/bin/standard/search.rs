@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+
+use crate::*;
+
+/// Parameters of `GameState::search_build_order`.
+#[derive(Clone, Copy)]
+pub struct SearchParams {
+    /// Number of build orders we keep extending at each step.
+    pub beam_width: usize,
+    /// Maximum number of actions in a build order.
+    pub max_len: usize,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self {
+            beam_width: 8,
+            max_len: 12,
+        }
+    }
+}
+
+/// The best build order found by `GameState::search_build_order`.
+pub struct SearchResult {
+    pub actions: Vec<Action>,
+    pub report: SimReport,
+    /// Number of build orders we simulated.
+    pub evaluated: usize,
+    /// The build order as the body of `play`.
+    pub rust: String,
+    /// The build order as a `[build_order]` section for `rustorio.toml`.
+    pub toml: String,
+}
+
+impl GameState {
+    /// Search for a good build order using the simulator: a beam search over build orders, scored
+    /// by simulated victory tick. At each step every build order in the beam is extended by one
+    /// action; we keep the `beam_width` best results.
+    pub fn search_build_order(&mut self, params: SearchParams) -> Result<SearchResult, SimError> {
+        let registry = BuildRegistry::new();
+        // Only the actions that `play` knows how to perform.
+        let candidates = Simulation::new(self)
+            .entity_actions(DEFAULT_BUILD_ORDER_PRIORITY)
+            .into_iter()
+            .filter(|a| registry.for_action(a).is_some())
+            .collect_vec();
+        let mut evaluated = 0;
+        let mut evaluate = |state: &mut GameState, actions: Vec<Action>| {
+            evaluated += 1;
+            let report = state.simulate(&actions)?;
+            Ok((report.victory_tick.unwrap_or(u64::MAX), actions))
+        };
+
+        let mut best = evaluate(self, vec![])?;
+        let mut beam = vec![best.clone()];
+        for len in 1..=params.max_len {
+            let mut seen = HashSet::new();
+            let mut next = vec![];
+            for (_, actions) in &beam {
+                let can_complete = actions
+                    .last()
+                    .is_some_and(|a| !matches!(a, Action::Complete(_)));
+                let extensions = candidates
+                    .iter()
+                    .cloned()
+                    .chain(can_complete.then_some(Action::Complete(None)));
+                for action in extensions {
+                    let mut actions = actions.clone();
+                    actions.push(action);
+                    if seen.insert(actions.clone()) {
+                        next.push(evaluate(self, actions)?);
+                    }
+                }
+            }
+            // Stable, so shorter build orders win ties.
+            next.sort_by_key(|(score, _)| *score);
+            next.truncate(params.beam_width);
+            if let Some(first) = next.first()
+                && first.0 < best.0
+            {
+                best = first.clone();
+            }
+            eprintln!(
+                "search: length {len}, best victory tick {}",
+                display_score(best.0)
+            );
+            beam = next;
+        }
+
+        let (_, actions) = best;
+        let report = self.simulate(&actions)?;
+        Ok(SearchResult {
+            rust: to_rust(&registry, &actions),
+            toml: registry.to_toml(&actions, DEFAULT_BUILD_ORDER_PRIORITY),
+            actions,
+            report,
+            evaluated,
+        })
+    }
+}
+
+fn display_score(score: u64) -> String {
+    if score == u64::MAX {
+        "none".to_string()
+    } else {
+        score.to_string()
+    }
+}
+
+/// Format a build order as Rust statements to paste into `play`.
+fn to_rust(registry: &BuildRegistry, actions: &[Action]) -> String {
+    // The handles that each `Complete` waits on.
+    let mut completes = HashMap::new();
+    let mut pending = vec![];
+    for (i, action) in actions.iter().enumerate() {
+        match action {
+            Action::AddMachine { producer, .. } => pending.push((producer.clone(), i)),
+            Action::Complete(producer) => {
+                completes.insert(i, take_completed(&mut pending, producer.as_ref()));
+            }
+            Action::ScaleUp { .. } => {}
+        }
+    }
+    let completed: HashSet<usize> = completes.values().flatten().copied().collect();
+
+    let mut lines = vec![];
+    for (i, action) in actions.iter().enumerate() {
+        match action {
+            Action::ScaleUp { p, .. } | Action::AddMachine { p, .. } => {
+                let m = registry.for_action(action).unwrap();
+                let call = format!("self.{}::<{}>(Priority({}));", m.method, m.arg, p.0);
+                if completed.contains(&i) {
+                    lines.push(format!("let h{i} = {call}"));
+                } else {
+                    lines.push(call);
+                }
+            }
+            Action::Complete(_) => {
+                for h in &completes[&i] {
+                    lines.push(format!("self.complete(h{h});"));
+                }
+            }
+        }
+    }
+    lines.into_iter().map(|line| line + "\n").collect()
+}
@@ -0,0 +1,797 @@
+use std::{collections::HashSet, fmt};
+
+use crate::*;
+
+/// One step of a build order, mirroring the calls that `GameState::play` makes. Producers are
+/// named by `Producer::name`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// `GameState::scale_up`: build one more producing entity, counted in `scaling_up`.
+    ScaleUp { producer: String, p: Priority },
+    /// `GameState::add_machine`: build one more producing entity, without telling the producer.
+    AddMachine { producer: String, p: Priority },
+    /// Wait until machines are built, like `GameState::complete` on the handles that
+    /// `add_machine` returns: the last one added for that producer, or with `None` all those added
+    /// since the previous `Complete`. Scale-ups don't have a handle so we don't wait for them.
+    Complete(Option<String>),
+}
+
+/// Take the handles that `Action::Complete(producer)` waits for out of `pending`, which holds the
+/// handles of the `Action::AddMachine`s so far along with their producer.
+pub fn take_completed<K: PartialEq, H>(pending: &mut Vec<(K, H)>, producer: Option<&K>) -> Vec<H> {
+    match producer {
+        None => pending.drain(..).map(|(_, h)| h).collect(),
+        Some(producer) => match pending.iter().rposition(|(key, _)| key == producer) {
+            Some(i) => vec![pending.remove(i).1],
+            None => vec![],
+        },
+    }
+}
+
+/// The outcome of a simulation.
+pub struct SimReport {
+    /// The tick at which we'd get the target, `Victory` unless we used `Simulation::for_target`.
+    /// `None` if we got stuck or ran out of ticks.
+    pub victory_tick: Option<u64>,
+    /// The tick the simulation stopped at.
+    pub end_tick: u64,
+    /// The number of producing entities of each producer, every time one of them changed.
+    pub machine_counts: Vec<(u64, IndexMap<String, u32>)>,
+}
+
+/// Why a build order can't be simulated.
+#[derive(Debug)]
+pub enum SimError {
+    /// No producer has that name.
+    UnknownProducer(String),
+    /// The producer has no producing entity to build.
+    NoEntity(String),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::UnknownProducer(name) => write!(f, "unknown producer `{name}`"),
+            SimError::NoEntity(name) => write!(f, "can't scale up `{name}`: nothing to build"),
+        }
+    }
+}
+
+impl fmt::Display for SimReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.victory_tick {
+            Some(tick) => writeln!(f, "predicted victory at tick {tick}")?,
+            None => writeln!(f, "no victory, stopped at tick {}", self.end_tick)?,
+        }
+        for (tick, counts) in &self.machine_counts {
+            let counts = counts
+                .iter()
+                .filter(|(_, n)| **n != 0)
+                .map(|(name, n)| format!("{name} x{n}"))
+                .format(", ");
+            writeln!(f, "{tick:>8}: {counts}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Index into `Simulation::producers`.
+type ProducerIdx = usize;
+/// Index into `Simulation::requests`.
+type RequestId = usize;
+
+/// An `Action` with its producers looked up, see `Simulation::resolve`.
+enum SimAction {
+    AddEntity {
+        producer: ProducerIdx,
+        p: Priority,
+        scale_up: bool,
+    },
+    Complete(Option<ProducerIdx>),
+}
+
+/// What happens once all the parts of a request have arrived.
+enum Then {
+    /// Count as one part of another request.
+    Part(RequestId),
+    /// Give the inputs to the producer, whose output counts as one part of `parent`.
+    Feed {
+        producer: ProducerIdx,
+        p: Priority,
+        parent: RequestId,
+    },
+    /// Add a producing entity to the producer.
+    AddEntity {
+        producer: ProducerIdx,
+        scale_up: bool,
+    },
+    /// The item is now available to everyone who asked.
+    Unlock(GraphNode),
+    /// A producer output of the item goes to its pool.
+    Stock(GraphNode),
+    Nothing,
+}
+
+struct Request {
+    /// Number of parts we're still waiting for. Done at 0.
+    missing: u32,
+    then: Then,
+}
+
+/// When a producer gets crafted by hand.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HandMode {
+    /// While it has no producing entity, like `Territory` and `MultiMachine`.
+    WithoutEntity,
+    /// Always, like `HandCrafter`.
+    Always,
+}
+
+struct SimProducer {
+    name: String,
+    craft_time: u64,
+    always_busy: bool,
+    hand: Option<HandMode>,
+    /// `None` if there is nothing to build to scale up.
+    entity: Option<GraphNode>,
+    max_parallelism: u32,
+    parallelism: u32,
+    scaling_up: u32,
+    /// The requests waiting for an output, keyed like in `ProducerWithQueue`.
+    queue: WaiterQueue<(RequestId, Priority)>,
+    /// Number of inputs fed that no entity has started on yet.
+    fed: u32,
+    /// One entry per busy entity: the tick at which it yields its next output.
+    busy: Vec<u64>,
+    /// Outputs nobody was waiting for.
+    buffer: u32,
+}
+
+/// A `NodeKind` without the parts we only need at startup.
+#[derive(Clone, Copy)]
+enum SimKind {
+    Assembled,
+    Produced {
+        producer: ProducerIdx,
+        output_amount: u32,
+    },
+    Unlock,
+}
+
+struct SimNode {
+    name: String,
+    kind: SimKind,
+    /// What it takes to make the node: one output for produced nodes, one item otherwise. Unlocks
+    /// count as one.
+    inputs: Vec<(GraphNode, u32)>,
+}
+
+/// An offline model of the crafting logic, to evaluate build orders in milliseconds instead of
+/// playing a whole game. Build it from a `GameState` with `Simulation::new`, then `run` a build
+/// order.
+///
+/// The model is read off the `ResourceGraph`, so it follows the same `Makeable`/`Producer`
+/// structure as the real thing: bundles wait by priority in the pool of their item, which holds
+/// the stock and gets the outputs of one feed per producer output. Producers serve their feeds by
+/// priority too. Scale-ups follow the same heuristic one priority level above their waiters, and
+/// producers in `Producers::with_hand_producers` are crafted by hand while they have no producing
+/// entity. Known approximations:
+/// - crafting by hand doesn't delay handing out the outputs of machines;
+/// - labs aren't carried over when researching `SteelSmelting` switches technologies;
+/// - `PointsTechnology` is unlocked one priority level above its requester, like other unlocks.
+pub struct Simulation {
+    now: u64,
+    /// Give up after that tick.
+    pub tick_limit: u64,
+    aging: Aging,
+    nodes: HashMap<GraphNode, SimNode>,
+    /// A placeholder that needs one of what we're making, see `ResourceGraph::rooted`.
+    target: GraphNode,
+    producers: Vec<SimProducer>,
+    /// The producers that get crafted by hand, in the order `with_hand_producers` looks at them.
+    hand_order: Vec<ProducerIdx>,
+    /// The craft by hand in progress, and when it finishes.
+    hand_job: Option<(ProducerIdx, u64)>,
+    stock: HashMap<GraphNode, u32>,
+    /// The requests waiting in the pool of each produced item, with the amount they need. Keyed
+    /// like in `ResourceWithQueue`.
+    pool_waiters: HashMap<GraphNode, WaiterQueue<(RequestId, u32)>>,
+    unlocked: HashSet<GraphNode>,
+    /// The requests waiting for each unlock in progress.
+    unlock_waiters: HashMap<GraphNode, Vec<RequestId>>,
+    requests: Vec<Request>,
+    machine_counts: Vec<(u64, IndexMap<String, u32>)>,
+}
+
+impl Simulation {
+    /// Snapshot the state of the game, to predict when we'd get `Victory`.
+    pub fn new(state: &mut GameState) -> Self {
+        Self::for_target::<Victory>(state)
+    }
+
+    /// Snapshot the state of the game, to predict when we'd get a `T` instead of `Victory`.
+    pub fn for_target<T: Makeable>(state: &mut GameState) -> Self {
+        let (graph, target) = ResourceGraph::rooted::<T>();
+        let graph = &graph;
+
+        let mut hand = vec![];
+        let _ = state.producers.with_hand_producers(|p| {
+            hand.push((p.producer_id(), p.can_craft_automatically()));
+            ControlFlow::Continue(())
+        });
+
+        let mut producer_ids: HashMap<TypeId, ProducerIdx> = HashMap::new();
+        let mut producers: Vec<SimProducer> = vec![];
+        let mut nodes = HashMap::new();
+        let mut stock = HashMap::new();
+        let mut unlocked = HashSet::new();
+        for node in graph.nodes() {
+            let mut per_output = 1;
+            let kind = match graph.kind(node) {
+                NodeKind::Assembled => SimKind::Assembled,
+                NodeKind::Produced(info) => {
+                    stock.insert(node, (info.stock)(&mut state.resources));
+                    per_output = info.output_amount;
+                    let idx = *producer_ids.entry(info.producer).or_insert_with(|| {
+                        let existing = state.producers.get(info.producer);
+                        producers.push(SimProducer {
+                            name: info.name.clone(),
+                            craft_time: info.craft_time,
+                            always_busy: info.always_busy,
+                            hand: None,
+                            entity: graph.contains(info.entity).then_some(info.entity),
+                            max_parallelism: existing.map_or(u32::MAX, |p| p.max_parallelism()),
+                            parallelism: existing.map_or(0, |p| p.available_parallelism()),
+                            scaling_up: 0,
+                            queue: Default::default(),
+                            fed: 0,
+                            busy: vec![],
+                            buffer: 0,
+                        });
+                        producers.len() - 1
+                    });
+                    SimKind::Produced {
+                        producer: idx,
+                        output_amount: info.output_amount,
+                    }
+                }
+                NodeKind::Unlock { available, .. } => {
+                    if available(&mut state.resources) {
+                        unlocked.insert(node);
+                    }
+                    SimKind::Unlock
+                }
+            };
+            let inputs = graph
+                .edges(node)
+                .filter_map(|(tgt, w)| {
+                    if w.is_zero() {
+                        // Either an unlock, or the crafting entity of a producer.
+                        matches!(graph.kind(tgt), NodeKind::Unlock { .. }).then_some((tgt, 1))
+                    } else {
+                        Some((tgt, (w * per_output).ceil() as u32))
+                    }
+                })
+                .collect();
+            let name = graph.name(node).to_string();
+            nodes.insert(node, SimNode { name, kind, inputs });
+        }
+
+        let mut hand_order = vec![];
+        for (id, can_craft_automatically) in hand {
+            let Some(&idx) = producer_ids.get(&id) else {
+                continue;
+            };
+            let p = &mut producers[idx];
+            if !can_craft_automatically && p.parallelism != 0 {
+                // The entities of a hand crafter are the hands.
+                p.hand = Some(HandMode::Always);
+                p.parallelism = 0;
+                p.max_parallelism = 0;
+            } else {
+                p.hand = Some(HandMode::WithoutEntity);
+            }
+            hand_order.push(idx);
+        }
+
+        let mut sim = Self {
+            now: state.tick.cur(),
+            tick_limit: u64::MAX,
+            aging: state.producers.aging,
+            nodes,
+            target,
+            producers,
+            hand_order,
+            hand_job: None,
+            stock,
+            pool_waiters: Default::default(),
+            unlocked,
+            unlock_waiters: Default::default(),
+            requests: vec![],
+            machine_counts: vec![],
+        };
+        sim.record_machine_counts();
+        sim
+    }
+
+    /// Index of the producer with that name.
+    fn producer(&self, name: &str) -> Option<ProducerIdx> {
+        self.producers.iter().position(|p| p.name == name)
+    }
+
+    /// The name of what we build to scale up the producer, if there is something to build.
+    pub fn entity_name(&self, producer: &str) -> Option<&str> {
+        let entity = self.producers[self.producer(producer)?].entity?;
+        Some(&self.nodes[&entity].name)
+    }
+
+    /// The actions that build one more producing entity for some producer, the way `play` would:
+    /// `add_miner` (a scale-up) for territories, `add_machine` for the rest.
+    pub fn entity_actions(&self, p: Priority) -> Vec<Action> {
+        self.producers
+            .iter()
+            .filter(|producer| producer.entity.is_some())
+            .filter(|producer| producer.parallelism < producer.max_parallelism)
+            .map(|producer| {
+                let producer_name = producer.name.clone();
+                if producer.always_busy {
+                    Action::ScaleUp {
+                        producer: producer_name,
+                        p,
+                    }
+                } else {
+                    Action::AddMachine {
+                        producer: producer_name,
+                        p,
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Look up the producers named in the build order, so that a typo fails before we simulate
+    /// anything.
+    fn resolve(&self, actions: &[Action]) -> Result<Vec<SimAction>, SimError> {
+        let producer = |name: &String| {
+            self.producer(name)
+                .ok_or_else(|| SimError::UnknownProducer(name.clone()))
+        };
+        actions
+            .iter()
+            .map(|action| {
+                Ok(match action {
+                    Action::ScaleUp { producer: name, p }
+                    | Action::AddMachine { producer: name, p } => {
+                        let idx = producer(name)?;
+                        if self.producers[idx].entity.is_none() {
+                            return Err(SimError::NoEntity(name.clone()));
+                        }
+                        SimAction::AddEntity {
+                            producer: idx,
+                            p: *p,
+                            scale_up: matches!(action, Action::ScaleUp { .. }),
+                        }
+                    }
+                    Action::Complete(name) => {
+                        SimAction::Complete(name.as_ref().map(producer).transpose()?)
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Follow the build order, then make the target at priority 0 like `play` does. Fails before
+    /// simulating anything if the build order names a producer we don't know.
+    pub fn run(mut self, actions: &[Action]) -> Result<SimReport, SimError> {
+        let actions = self.resolve(actions)?;
+        let mut pending = vec![];
+        for action in actions {
+            match action {
+                SimAction::AddEntity {
+                    producer,
+                    p,
+                    scale_up,
+                } => {
+                    let id = self.add_entity(producer, p, scale_up)?;
+                    if !scale_up {
+                        pending.push((producer, id));
+                    }
+                }
+                SimAction::Complete(producer) => {
+                    let ids = take_completed(&mut pending, producer.as_ref());
+                    if !self.run_until(|sim| ids.iter().all(|&id| sim.is_done(id))) {
+                        return Ok(self.report(false));
+                    }
+                }
+            }
+        }
+        let target = self.target;
+        let id = self.request(Then::Nothing, |sim, id| {
+            sim.need(target, 1, Priority(0), id)
+        });
+        let won = self.run_until(|sim| sim.is_done(id));
+        Ok(self.report(won))
+    }
+
+    fn report(self, won: bool) -> SimReport {
+        SimReport {
+            victory_tick: won.then_some(self.now),
+            end_tick: self.now,
+            machine_counts: self.machine_counts,
+        }
+    }
+
+    fn record_machine_counts(&mut self) {
+        let counts = self
+            .producers
+            .iter()
+            .filter(|p| p.hand != Some(HandMode::Always))
+            .map(|p| (p.name.clone(), p.parallelism))
+            .sorted()
+            .collect();
+        if let Some((tick, _)) = self.machine_counts.last()
+            && *tick == self.now
+        {
+            self.machine_counts.pop();
+        }
+        self.machine_counts.push((self.now, counts));
+    }
+
+    fn is_done(&self, id: RequestId) -> bool {
+        self.requests[id].missing == 0
+    }
+
+    /// Create a request; `parts` issues its parts with `need`.
+    fn request(&mut self, then: Then, parts: impl FnOnce(&mut Self, RequestId)) -> RequestId {
+        let id = self.requests.len();
+        // Count one extra part so that the request doesn't complete before all its parts have been
+        // issued.
+        self.requests.push(Request { missing: 1, then });
+        parts(self, id);
+        self.part_done(id);
+        id
+    }
+
+    fn part_done(&mut self, id: RequestId) {
+        let request = &mut self.requests[id];
+        request.missing -= 1;
+        if request.missing != 0 {
+            return;
+        }
+        match std::mem::replace(&mut request.then, Then::Nothing) {
+            Then::Part(parent) => self.part_done(parent),
+            Then::Feed {
+                producer,
+                p,
+                parent,
+            } => self.feed(producer, p, parent),
+            Then::AddEntity { producer, scale_up } => {
+                let producer = &mut self.producers[producer];
+                producer.parallelism += 1;
+                if scale_up {
+                    producer.scaling_up -= 1;
+                }
+                self.record_machine_counts();
+            }
+            Then::Unlock(node) => {
+                self.unlocked.insert(node);
+                for waiter in self.unlock_waiters.remove(&node).unwrap_or_default() {
+                    self.part_done(waiter);
+                }
+            }
+            Then::Stock(node) => {
+                let SimKind::Produced { output_amount, .. } = self.nodes[&node].kind else {
+                    unreachable!()
+                };
+                *self.stock.get_mut(&node).unwrap() += output_amount;
+                self.serve_pool(node);
+            }
+            Then::Nothing => {}
+        }
+    }
+
+    /// Make `amount` of `node` as one part of `parent`. Mirrors the `make_to` implementations.
+    fn need(&mut self, node: GraphNode, amount: u32, p: Priority, parent: RequestId) {
+        self.requests[parent].missing += 1;
+        let SimNode { kind, inputs, .. } = &self.nodes[&node];
+        let (kind, inputs) = (*kind, inputs.clone());
+        match kind {
+            SimKind::Assembled => {
+                self.request(Then::Part(parent), |sim, id| {
+                    for (input, n) in inputs {
+                        sim.need(input, n * amount, p, id);
+                    }
+                });
+            }
+            SimKind::Produced {
+                producer,
+                output_amount,
+            } => {
+                let stock = self.stock.get_mut(&node).unwrap();
+                let waiters = self.pool_waiters.entry(node).or_default();
+                if waiters.is_empty() && *stock >= amount {
+                    *stock -= amount;
+                    self.part_done(parent);
+                    return;
+                }
+                waiters.push(self.aging.sort_key(p, self.now), (parent, amount));
+                self.serve_pool(node);
+                // One feed per producer output, like `Bundle::make_to`. The outputs go to the
+                // pool, not to us.
+                for _ in 0..amount.div_ceil(output_amount) {
+                    let output = self.requests.len();
+                    self.requests.push(Request {
+                        missing: 1,
+                        then: Then::Stock(node),
+                    });
+                    let then = Then::Feed {
+                        producer,
+                        p,
+                        parent: output,
+                    };
+                    self.request(then, |sim, id| {
+                        for &(input, n) in &inputs {
+                            sim.need(input, n, p, id);
+                        }
+                    });
+                }
+            }
+            SimKind::Unlock => {
+                if self.unlocked.contains(&node) {
+                    self.part_done(parent);
+                    return;
+                }
+                let waiters = self.unlock_waiters.entry(node).or_default();
+                waiters.push(parent);
+                if waiters.len() == 1 {
+                    // Like a scale-up of `OnceMaker`.
+                    let p = Priority(p.0 + 1);
+                    self.request(Then::Unlock(node), |sim, id| {
+                        for (input, n) in inputs {
+                            sim.need(input, n, p, id);
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    /// Hand out the stock of `node` to its pool waiters, like `ResourceWithQueue::serve`.
+    fn serve_pool(&mut self, node: GraphNode) {
+        loop {
+            let stock = self.stock.get_mut(&node).unwrap();
+            let waiters = self.pool_waiters.get_mut(&node).unwrap();
+            let Some(&(waiter, amount)) = waiters.peek() else {
+                return;
+            };
+            if *stock < amount {
+                return;
+            }
+            *stock -= amount;
+            waiters.pop();
+            self.part_done(waiter);
+        }
+    }
+
+    fn feed(&mut self, producer: ProducerIdx, p: Priority, parent: RequestId) {
+        let key = self.aging.sort_key(p, self.now);
+        let producer = &mut self.producers[producer];
+        if producer.always_busy {
+            if producer.buffer > 0 {
+                producer.buffer -= 1;
+                self.part_done(parent);
+                return;
+            }
+        } else {
+            producer.fed += 1;
+        }
+        producer.queue.push(key, (parent, p));
+    }
+
+    /// Build one more entity for the producer.
+    fn add_entity(
+        &mut self,
+        producer: ProducerIdx,
+        p: Priority,
+        scale_up: bool,
+    ) -> Result<RequestId, SimError> {
+        let Some(entity) = self.producers[producer].entity else {
+            return Err(SimError::NoEntity(self.producers[producer].name.clone()));
+        };
+        if scale_up {
+            self.producers[producer].scaling_up += 1;
+        }
+        Ok(
+            self.request(Then::AddEntity { producer, scale_up }, |sim, id| {
+                sim.need(entity, 1, p, id)
+            }),
+        )
+    }
+
+    /// Mirrors `ProducerWithQueue::scale_up_if_needed`.
+    fn scale_up_if_needed(&mut self, idx: ProducerIdx) -> bool {
+        let producer = &self.producers[idx];
+        let load = producer.queue.len() as u32;
+        let parallelism = producer.parallelism + producer.scaling_up;
+        if load == 0 || parallelism >= producer.max_parallelism {
+            return false;
+        }
+        if parallelism != 0 && !load_warrants_scale_up(load, parallelism, producer.scaling_up) {
+            return false;
+        }
+        let (_, p) = *producer.queue.peek().unwrap();
+        // Fails if there is nothing to build.
+        self.add_entity(idx, Priority(p.0 + 1), true).is_ok()
+    }
+
+    /// Start all the work that can start now.
+    fn settle(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for idx in 0..self.producers.len() {
+                changed |= self.scale_up_if_needed(idx);
+                let now = self.now;
+                let producer = &mut self.producers[idx];
+                while (producer.busy.len() as u32) < producer.parallelism
+                    && (producer.always_busy || producer.fed > 0)
+                {
+                    if !producer.always_busy {
+                        producer.fed -= 1;
+                    }
+                    producer.busy.push(now + producer.craft_time);
+                }
+            }
+            if self.hand_job.is_none() {
+                for &idx in &self.hand_order {
+                    let producer = &mut self.producers[idx];
+                    let needs_hand = !producer.queue.is_empty()
+                        && (producer.hand == Some(HandMode::Always) || producer.parallelism == 0)
+                        && (producer.always_busy || producer.fed > 0);
+                    if needs_hand {
+                        if !producer.always_busy {
+                            producer.fed -= 1;
+                        }
+                        self.hand_job = Some((idx, self.now + producer.craft_time));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn next_event(&self) -> Option<u64> {
+        self.producers
+            .iter()
+            .flat_map(|p| p.busy.iter().copied())
+            .chain(self.hand_job.map(|(_, t)| t))
+            .min()
+    }
+
+    /// Hand out the outputs due at `now`.
+    fn process_events(&mut self) {
+        let now = self.now;
+        let mut outputs = vec![];
+        if let Some((idx, t)) = self.hand_job
+            && t == now
+        {
+            self.hand_job = None;
+            outputs.push(idx);
+        }
+        for (idx, producer) in self.producers.iter_mut().enumerate() {
+            let craft_time = producer.craft_time;
+            let always_busy = producer.always_busy;
+            producer.busy.retain_mut(|t| {
+                if *t != now {
+                    return true;
+                }
+                outputs.push(idx);
+                // Miners start on the next output right away.
+                *t += craft_time;
+                always_busy
+            });
+        }
+        for idx in outputs {
+            match self.producers[idx].queue.pop() {
+                Some((waiter, _)) => self.part_done(waiter),
+                None => self.producers[idx].buffer += 1,
+            }
+        }
+    }
+
+    /// Advance time until `done` holds. Returns `false` if we got stuck or hit `tick_limit`.
+    fn run_until(&mut self, done: impl Fn(&Self) -> bool) -> bool {
+        loop {
+            self.settle();
+            if done(self) {
+                return true;
+            }
+            match self.next_event() {
+                Some(t) if t <= self.tick_limit => self.now = t,
+                _ => return false,
+            }
+            self.process_events();
+        }
+    }
+}
+
+impl GameState {
+    /// Predict how the game would go if we followed this build order from now on.
+    pub fn simulate(&mut self, actions: &[Action]) -> Result<SimReport, SimError> {
+        Simulation::new(self).run(actions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::with_state;
+
+    /// A simulation where nothing scales up, so that everything gets crafted by hand.
+    fn by_hand<T: Makeable>(state: &mut GameState) -> Simulation {
+        let mut sim = Simulation::for_target::<T>(state);
+        for producer in &mut sim.producers {
+            producer.max_parallelism = producer.parallelism;
+        }
+        sim
+    }
+
+    /// Number of ticks it takes one entity to make one `T`.
+    fn ticks_per_item<T: BundleMakeable>(state: &mut GameState) -> Ratio {
+        // One item per tick takes that many entities.
+        let plan = state.machine_ratios::<T>(Ratio::ONE);
+        let [producer] = &plan.producers[..] else {
+            panic!("not a raw item")
+        };
+        producer.entities
+    }
+
+    #[test]
+    fn mining_by_hand_matches_the_rates() {
+        with_state(|mut state| {
+            let start = state.tick.cur();
+            let report = by_hand::<Bundle<IronOre, 10>>(&mut state).run(&[]).unwrap();
+            let ticks = report.victory_tick.unwrap() - start;
+            assert_eq!(
+                Ratio::from(ticks),
+                ticks_per_item::<IronOre>(&mut state) * 10
+            );
+        });
+    }
+
+    #[test]
+    fn crafts_by_hand_one_at_a_time() {
+        with_state(|mut state| {
+            let start = state.tick.cur();
+            let report = by_hand::<(Bundle<IronOre, 3>, Bundle<CopperOre, 2>)>(&mut state)
+                .run(&[])
+                .unwrap();
+            let ticks = report.victory_tick.unwrap() - start;
+            let expected = ticks_per_item::<IronOre>(&mut state) * 3
+                + ticks_per_item::<CopperOre>(&mut state) * 2;
+            assert_eq!(Ratio::from(ticks), expected);
+        });
+    }
+
+    #[test]
+    fn unknown_producers_fail_upfront() {
+        with_state(|mut state| {
+            let actions = [
+                Action::Complete(None),
+                Action::Complete(Some("Furnace<IronSmeltin>".to_string())),
+            ];
+            let err = Simulation::new(&mut state).run(&actions).err().unwrap();
+            assert!(
+                matches!(err, SimError::UnknownProducer(name) if name == "Furnace<IronSmeltin>")
+            );
+        });
+    }
+
+    #[test]
+    fn leaves_the_game_graph_alone() {
+        with_state(|mut state| {
+            state.simulate(&[]).unwrap();
+            assert_eq!(state.graph.nodes().count(), 0);
+        });
+    }
+}
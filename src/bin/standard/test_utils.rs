@@ -0,0 +1,31 @@
+use std::{cell::RefCell, panic};
+
+use crate::*;
+
+type TestBody = Box<dyn FnOnce(GameState)>;
+
+thread_local! {
+    static TEST: RefCell<Option<TestBody>> = RefCell::new(None);
+}
+
+/// Unwinds out of `rustorio::play` once the test is done.
+struct TestDone;
+
+/// Run `f` on a fresh game. The engine only hands out a `Tick` to the callback of
+/// `rustorio::play`, so we run `f` from there and unwind back out when it returns.
+pub fn with_state(f: impl FnOnce(GameState) + 'static) {
+    fn run(
+        tick: Tick,
+        starting_resources: StartingResources,
+    ) -> (Tick, <GameMode as rustorio::GameMode>::VictoryResources) {
+        let f = TEST.take().expect("no test to run");
+        f(GameState::new(tick, starting_resources));
+        panic::resume_unwind(Box::new(TestDone))
+    }
+    TEST.set(Some(Box::new(f)));
+    match panic::catch_unwind(|| rustorio::play::<GameMode>(run)) {
+        Err(payload) if payload.is::<TestDone>() => {}
+        Err(payload) => panic::resume_unwind(payload),
+        Ok(()) => panic!("`rustorio::play` returned without running the test"),
+    }
+}
@@ -0,0 +1,210 @@
+use std::{cmp::max, fs, io, path::Path};
+
+use serde_json::json;
+
+use crate::*;
+
+/// Identifies a request recorded in `Tasks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(usize);
+
+/// A single request for a resource.
+pub struct Task {
+    /// The request that spawned this one, if any.
+    pub parent: Option<TaskId>,
+    /// Name of the requested resource.
+    pub name: String,
+    /// Shared with the waiters this request put in producer queues, so that raising it takes
+    /// effect there too.
+    pub priority: SharedPriority,
+    /// The requests spawned by this one.
+    pub children: Vec<TaskId>,
+    pub start: u64,
+    /// `None` while the request is pending.
+    pub finish: Option<u64>,
+    pub cancelled: bool,
+}
+
+/// Records the tree of requests spawned by `GameState::make_to`.
+#[derive(Default)]
+pub struct Tasks {
+    tasks: Vec<Task>,
+    /// The requests that are currently being set up. Requests are spawned synchronously by their
+    /// parent, so the top of the stack is the parent of any new request.
+    stack: Vec<TaskId>,
+}
+
+impl Tasks {
+    /// Record a new request, child of the request currently being set up. It inherits the
+    /// priority of its parent if that is higher than `p`.
+    pub fn start(&mut self, name: String, tick: u64, p: Priority) -> TaskId {
+        let id = TaskId(self.tasks.len());
+        let parent = self.current();
+        let p = match parent {
+            Some(parent) => max(p, self.get(parent).priority.get()),
+            None => p,
+        };
+        if let Some(parent) = parent {
+            self.tasks[parent.0].children.push(id);
+        }
+        self.tasks.push(Task {
+            parent,
+            name,
+            priority: SharedPriority::new(p),
+            children: vec![],
+            start: tick,
+            finish: None,
+            cancelled: false,
+        });
+        id
+    }
+    /// The request currently being set up, if any.
+    pub fn current(&self) -> Option<TaskId> {
+        self.stack.last().copied()
+    }
+    pub fn finish(&mut self, id: TaskId, tick: u64) {
+        self.tasks[id.0].finish = Some(tick);
+    }
+    pub fn get(&self, id: TaskId) -> &Task {
+        &self.tasks[id.0]
+    }
+    pub fn is_finished(&self, id: TaskId) -> bool {
+        self.get(id).finish.is_some()
+    }
+    pub fn is_cancelled(&self, id: TaskId) -> bool {
+        self.get(id).cancelled
+    }
+
+    /// The pending requests in the tree rooted at `id`.
+    fn pending_subtree(&self, id: TaskId) -> Vec<TaskId> {
+        let mut pending = vec![];
+        let mut todo = vec![id];
+        while let Some(id) = todo.pop() {
+            let task = self.get(id);
+            if task.finish.is_none() {
+                pending.push(id);
+            }
+            todo.extend(&task.children);
+        }
+        pending
+    }
+
+    /// Raise the priority of a pending request and of all its pending sub-requests to at least
+    /// `p`. Returns whether anything changed.
+    pub fn bump(&mut self, id: TaskId, p: Priority) -> bool {
+        let mut changed = false;
+        for id in self.pending_subtree(id) {
+            changed |= self.get(id).priority.raise(p);
+        }
+        changed
+    }
+    /// Set the priority of a pending request and of all its pending sub-requests to `p`.
+    pub fn set_priority(&mut self, id: TaskId, p: Priority) {
+        for id in self.pending_subtree(id) {
+            self.get(id).priority.set(p);
+        }
+    }
+    /// Mark a pending request and all its pending sub-requests as cancelled.
+    pub fn cancel(&mut self, id: TaskId, tick: u64) {
+        for id in self.pending_subtree(id) {
+            let task = &mut self.tasks[id.0];
+            task.cancelled = true;
+            task.finish = Some(tick);
+        }
+    }
+
+    /// List the tasks in depth-first order, along with their depth in the tree.
+    fn depth_first(&self) -> Vec<(TaskId, usize)> {
+        let mut order = vec![];
+        let mut todo = (0..self.tasks.len())
+            .map(TaskId)
+            .filter(|&id| self.get(id).parent.is_none())
+            .rev()
+            .map(|id| (id, 0))
+            .collect_vec();
+        while let Some((id, depth)) = todo.pop() {
+            order.push((id, depth));
+            let children = &self.get(id).children;
+            todo.extend(children.iter().rev().map(|&id| (id, depth + 1)));
+        }
+        order
+    }
+
+    /// Format the tasks as Chrome trace-event JSON, which can be loaded in `chrome://tracing` or
+    /// https://ui.perfetto.dev. One tick is shown as one microsecond. Each task gets its own row,
+    /// and rows are ordered so that children come right below their parent. Tasks still pending at
+    /// `now` are drawn up to `now`.
+    pub fn to_trace_events(&self, now: u64) -> serde_json::Value {
+        let events = self
+            .depth_first()
+            .into_iter()
+            .enumerate()
+            .flat_map(|(row, (id, depth))| {
+                let task = self.get(id);
+                let finish = task.finish.unwrap_or(now);
+                let name = format!("{}{}", "  ".repeat(depth), task.name);
+                [
+                    json!({
+                        "ph": "M",
+                        "name": "thread_name",
+                        "pid": 0,
+                        "tid": row,
+                        "args": { "name": name },
+                    }),
+                    json!({
+                        "ph": "X",
+                        "name": task.name,
+                        "pid": 0,
+                        "tid": row,
+                        "ts": task.start,
+                        "dur": finish - task.start,
+                        "args": {
+                            "id": id.0,
+                            "parent": task.parent.map(|p| p.0),
+                            "priority": task.priority.get().0,
+                            "finished": task.finish.is_some(),
+                            "cancelled": task.cancelled,
+                        },
+                    }),
+                ]
+            })
+            .collect_vec();
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+}
+
+impl GameState {
+    /// Run `f` with `id` as the parent of any request it spawns.
+    pub fn in_task<R>(&mut self, id: TaskId, f: impl FnOnce(&mut GameState) -> R) -> R {
+        self.tasks.stack.push(id);
+        let ret = f(self);
+        self.tasks.stack.pop();
+        ret
+    }
+
+    /// Sort the producer and pool queues again, e.g. after some priorities were raised.
+    pub fn resort_queues(&mut self) {
+        self.producers.resort();
+        self.resources.resort(self.producers.aging);
+    }
+
+    pub fn set_task_priority(&mut self, id: TaskId, p: Priority) {
+        self.tasks.set_priority(id, p);
+        self.resort_queues();
+    }
+    /// Abandon a request and all its sub-requests.
+    pub fn cancel_task(&mut self, id: TaskId) {
+        self.tasks.cancel(id, self.tick.cur());
+        // Dropping the waiters drops the partial results they were meant to complete, which
+        // salvages them.
+        self.producers.remove_cancelled(&self.tasks);
+        self.resources.remove_cancelled(&self.tasks);
+        self.restock_salvaged();
+    }
+
+    /// Write the task tree to `path` as Chrome trace-event JSON.
+    pub fn write_trace(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let trace = self.tasks.to_trace_events(self.tick.cur());
+        fs::write(path, serde_json::to_string(&trace)?)
+    }
+}
@@ -0,0 +1,167 @@
+use std::{collections::HashSet, fmt};
+
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::*;
+
+/// A reason why we could never make the target, see `GameState::validate_graph`.
+pub enum GraphError {
+    /// These items need each other as inputs.
+    Cycle(Vec<String>),
+    /// The producer of this item only exists if we start with it, and we didn't.
+    MissingProducer(String),
+    /// This unlock is only available if we start with it, and we didn't.
+    MissingUnlock(String),
+    /// `item` has no producing entity, and we can never build one.
+    NoEntity {
+        item: String,
+        entity: String,
+        /// What makes `entity` impossible to build.
+        because: Vec<String>,
+    },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Cycle(items) => {
+                write!(
+                    f,
+                    "these items need each other: {}",
+                    items.iter().format(", ")
+                )
+            }
+            GraphError::MissingProducer(item) => {
+                write!(
+                    f,
+                    "the producer of `{item}` isn't in the starting resources"
+                )
+            }
+            GraphError::MissingUnlock(item) => {
+                write!(f, "`{item}` isn't in the starting resources")
+            }
+            GraphError::NoEntity {
+                item,
+                entity,
+                because,
+            } => write!(
+                f,
+                "`{item}` can't be made: we can't build a `{entity}` because of {}",
+                because.iter().map(|name| format!("`{name}`")).format(", ")
+            ),
+        }
+    }
+}
+
+impl GameState {
+    /// Check that a `T` can be made from the starting resources: no item needs itself, and every
+    /// producer involved exists or can be built. Producers that have no entity yet count as
+    /// working if they can craft by hand. This catches mistakes in the resource graph before they
+    /// turn into a stall.
+    pub fn validate_graph<T: Makeable>(&mut self) -> Result<(), Vec<GraphError>> {
+        let mut graph = ResourceGraph::default();
+        T::add_nodes_to_graph(&mut graph);
+        let mut errors = vec![];
+
+        let name = |node| graph.name(node).to_string();
+        for cycle in graph.material_cycles() {
+            let names = cycle.into_iter().map(name).sorted().collect();
+            errors.push(GraphError::Cycle(names));
+        }
+
+        let nodes = graph
+            .nodes()
+            .sorted_by_key(|&node| name(node))
+            .collect_vec();
+        for &node in &nodes {
+            if let NodeKind::Produced(info) = graph.kind(node)
+                && info.from_start
+                && self.producers.get(info.producer).is_none()
+            {
+                errors.push(GraphError::MissingProducer(name(node)));
+            }
+        }
+        // Without those producers nothing works, and `with_hand_producers` would panic.
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut hand = HashSet::new();
+        let _ = self.producers.with_hand_producers(|p| {
+            hand.insert(p.producer_id());
+            ControlFlow::Continue(())
+        });
+        // The items we can never have, and the items each item can't do without.
+        let mut dead = HashSet::new();
+        let mut needs = IndexMap::new();
+        for node in nodes {
+            let mut required = graph
+                .edges(node)
+                .filter(|&(tgt, w)| graph.is_material_edge(tgt, w))
+                .map(|(tgt, _)| tgt)
+                .collect_vec();
+            match graph.kind(node) {
+                NodeKind::Assembled => {}
+                NodeKind::Produced(info) => {
+                    let existing = self.producers.get(info.producer);
+                    let working = existing.is_some_and(|p| p.available_parallelism() != 0)
+                        || hand.contains(&info.producer);
+                    if !working && graph.contains(info.entity) {
+                        required.push(info.entity);
+                    }
+                }
+                NodeKind::Unlock {
+                    available,
+                    from_start,
+                    ..
+                } => {
+                    if available(&mut self.resources) {
+                        required.clear();
+                    } else if from_start {
+                        dead.insert(node);
+                        errors.push(GraphError::MissingUnlock(name(node)));
+                    }
+                }
+            }
+            needs.insert(node, required);
+        }
+
+        // Spread to the items that need a dead item, until nothing changes.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (node, required) in &needs {
+                if !dead.contains(node) && required.iter().any(|r| dead.contains(r)) {
+                    dead.insert(*node);
+                    changed = true;
+                }
+            }
+        }
+        // Report the items that we can't make only because their producer can't get an entity.
+        // The items that need them follow.
+        let dead_needs = |node| {
+            needs[&node]
+                .iter()
+                .copied()
+                .filter(|r| dead.contains(r))
+                .collect_vec()
+        };
+        for &node in needs.keys() {
+            if let NodeKind::Produced(info) = graph.kind(node)
+                && dead_needs(node) == [info.entity]
+            {
+                errors.push(GraphError::NoEntity {
+                    item: name(node),
+                    entity: name(info.entity),
+                    because: dead_needs(info.entity).into_iter().map(name).collect(),
+                });
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
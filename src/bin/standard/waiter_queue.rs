@@ -0,0 +1,112 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+/// A priority queue that serves larger keys first, and items with the same key in the order they
+/// were pushed. Insertion and removal are `O(log n)`.
+pub struct WaiterQueue<T> {
+    heap: BinaryHeap<Entry<T>>,
+    /// Incremented on every push, to break ties in FIFO order.
+    next_seq: u64,
+}
+
+struct Entry<T> {
+    key: i64,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl<T> Eq for Entry<T> {}
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T> Ord for Entry<T> {
+    /// The max-heap pops the greatest entry: largest key, then smallest sequence number.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<T> Default for WaiterQueue<T> {
+    fn default() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            next_seq: 0,
+        }
+    }
+}
+
+impl<T> WaiterQueue<T> {
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+    pub fn push(&mut self, key: i64, item: T) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(Entry { key, seq, item });
+    }
+    /// The item that `pop` would return.
+    pub fn peek(&self) -> Option<&T> {
+        self.heap.peek().map(|e| &e.item)
+    }
+    pub fn pop(&mut self) -> Option<T> {
+        self.heap.pop().map(|e| e.item)
+    }
+    /// Iterate over the items in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.heap.iter().map(|e| &e.item)
+    }
+    /// Keep only the items for which `f` returns `true`. `O(n)`.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        self.heap.retain(|e| f(&e.item));
+    }
+    /// Recompute every key, keeping FIFO order among equal keys. `O(n)`.
+    pub fn rekey(&mut self, mut f: impl FnMut(&T) -> i64) {
+        let mut entries = std::mem::take(&mut self.heap).into_vec();
+        for e in &mut entries {
+            e.key = f(&e.item);
+        }
+        self.heap = BinaryHeap::from(entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(queue: &mut WaiterQueue<&'static str>) -> Vec<&'static str> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn larger_keys_first_then_fifo() {
+        let mut queue = WaiterQueue::default();
+        for (key, item) in [(0, "a"), (1, "b"), (0, "c"), (1, "d"), (-1, "e"), (0, "f")] {
+            queue.push(key, item);
+        }
+        assert_eq!(queue.peek(), Some(&"b"));
+        assert_eq!(drain(&mut queue), ["b", "d", "a", "c", "f", "e"]);
+    }
+
+    #[test]
+    fn rekey_and_retain_keep_fifo_ties() {
+        let mut queue = WaiterQueue::default();
+        for (key, item) in [(3, "a"), (2, "b"), (1, "c"), (0, "d")] {
+            queue.push(key, item);
+        }
+        queue.retain(|&item| item != "b");
+        queue.rekey(|_| 0);
+        assert_eq!(queue.len(), 3);
+        assert_eq!(drain(&mut queue), ["a", "c", "d"]);
+    }
+}